@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use crate::source::{ArchiveSource, SourceError};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 const SUPPLEMENTAL_SUFFIXES: &[&str] = &[
@@ -25,11 +26,132 @@ const SUPPLEMENTAL_SUFFIXES: &[&str] = &[
     ".s.",
 ];
 
+/// Suffixes Google Photos appends to the stem of edited media, by account language
+pub const DEFAULT_EDITED_SUFFIXES: &[&str] = &[
+    "-edited",
+    "-bearbeitet",
+    "-modifié",
+    "-redigeret",
+    "-redigerad",
+    "-redigert",
+    "-bewerkt",
+    "-editado",
+    "-modificato",
+    "-edytowane",
+    "-muokattu",
+];
+
+/// Video halves of Live Photos ("IMG_1234.HEIC" + "IMG_1234.MOV") and Motion Photos
+/// ("PXL_1234.MP.jpg" + "PXL_1234.MP"), which have no sidecar of their own
+const COMPANION_VIDEO_EXTENSIONS: &[&str] = &[".mov", ".mp4", ".mp"];
+
+/// Extensions of the still half of a Live Photo or Motion Photo, whose sidecar the video shares
+const COMPANION_STILL_EXTENSIONS: &[&str] = &[".heic", ".heif", ".jpg", ".jpeg"];
+
+/// Names of the file describing an album, in its folder, by account language
+const ALBUM_METADATA_FILE_NAMES: &[&str] = &[
+    "metadata.json",
+    "metadaten.json",
+    "métadonnées.json",
+    "metadatos.json",
+    "metadati.json",
+    "metadados.json",
+];
+
+/// Takeout cuts sidecar file names to 51 characters including ".json", which truncates
+/// the media name itself when it is long. Stems at least this long may have been cut.
+const TRUNCATED_SIDECAR_STEM_LENGTH: usize = 46;
+
+/// The `title` field of parsed sidecars, keyed by sidecar archive path
+pub type SidecarTitles = HashMap<String, String>;
+
+/// Checks if a JSON file name is long enough that Takeout may have truncated it
+/// into the media name, leaving none of the supplemental suffix behind
+fn is_truncated_sidecar_name(file_name: &str) -> bool {
+    let Some(stem) = file_name.strip_suffix(".json") else {
+        return false;
+    };
+    let (stem, _) = split_duplicate_counter(stem);
+    stem.chars().count() >= TRUNCATED_SIDECAR_STEM_LENGTH
+}
+
+/// Checks if a JSON file name is the sidecar name of a media file, cut inside the media name.
+/// Takeout names the sidecar "<media name>.supplemental-metadata" before cutting it, and
+/// moves the counter of numbered duplicates behind the cut, so the sidecar's stem must be a
+/// prefix of that name and carry the same counter.
+fn is_truncated_sidecar_of(sidecar_name: &str, media_name: &str) -> bool {
+    if !is_truncated_sidecar_name(sidecar_name) {
+        return false;
+    }
+    let Some(stem) = sidecar_name.strip_suffix(".json") else {
+        return false;
+    };
+    let (stem, sidecar_counter) = split_duplicate_counter(stem);
+    let (name, counter) = match relocate_duplicate_counter(media_name) {
+        Some((original_name, counter)) => (original_name, Some(counter)),
+        None => (media_name.to_string(), None),
+    };
+    let full_stem = format!("{}{}", name, SUPPLEMENTAL_SUFFIXES[0].trim_end_matches('.'));
+    sidecar_counter == counter && full_stem.starts_with(stem)
+}
+
 fn is_supplemental_metadata_path(path: &str) -> bool {
     let lower = path.to_lowercase();
+    let Some(stem) = lower.strip_suffix(".json") else {
+        return false;
+    };
+    // Sidecars of duplicate media carry the counter right before ".json"
+    let (stem, _) = split_duplicate_counter(stem);
     SUPPLEMENTAL_SUFFIXES
         .iter()
-        .any(|suffix| lower.ends_with(&format!("{}json", suffix)))
+        .any(|suffix| stem.ends_with(suffix.trim_end_matches('.')))
+}
+
+/// Checks if a path is the metadata file of an album, holding its title and description
+pub fn is_album_metadata_path(path: &str) -> bool {
+    let (_, file_name) = split_file_name(path);
+    let lower = file_name.to_lowercase();
+    ALBUM_METADATA_FILE_NAMES.contains(&lower.as_str())
+}
+
+/// Splits a trailing duplicate counter such as "(1)" off a name,
+/// e.g. "IMG_1234(1)" -> ("IMG_1234", Some("(1)"))
+fn split_duplicate_counter(name: &str) -> (&str, Option<&str>) {
+    if let Some(without_paren) = name.strip_suffix(')')
+        && let Some(open) = without_paren.rfind('(')
+    {
+        let digits = &without_paren[open + 1..];
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            return (&name[..open], Some(&name[open..]));
+        }
+    }
+    (name, None)
+}
+
+/// Splits an archive path into its directory (including the trailing slash) and file name
+fn split_file_name(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(slash) => path.split_at(slash + 1),
+        None => ("", path),
+    }
+}
+
+/// Splits a file name into its stem and extension (including the dot)
+fn split_extension(file_name: &str) -> (&str, &str) {
+    match file_name.rfind('.') {
+        Some(dot) if dot > 0 => file_name.split_at(dot),
+        _ => (file_name, ""),
+    }
+}
+
+/// Moves a duplicate counter from the media stem to after the extension, the way Takeout
+/// names duplicate sidecars, e.g. "IMG_1234(1).jpg" -> Some(("IMG_1234.jpg", "(1)"))
+fn relocate_duplicate_counter(file_name: &str) -> Option<(String, &str)> {
+    let (stem, extension) = split_extension(file_name);
+    match split_duplicate_counter(stem) {
+        (original_stem, Some(counter)) => Some((format!("{}{}", original_stem, extension), counter)),
+        _ => None,
+    }
 }
 
 /// Represents a file within an archive, abstracting over the archive format.
@@ -39,10 +161,12 @@ pub struct ArchiveFile {
     pub archive_path: String,
     /// The path to the archive file on disk that contains this file
     pub source_archive: PathBuf,
-    /// The index of this file within the archive (for zip files)
+    /// The handle of this file within its source, see [`crate::source::SourceEntry::index`]
     pub index: usize,
     /// File size in bytes
     pub size: u64,
+    /// When the file was last modified in its source, as a Unix timestamp
+    pub modified: Option<i64>,
 }
 
 impl ArchiveFile {
@@ -53,9 +177,16 @@ impl ArchiveFile {
             source_archive,
             index,
             size,
+            modified: None,
         }
     }
 
+    /// Sets when the file was last modified in its source
+    pub fn with_modified(mut self, modified: Option<i64>) -> Self {
+        self.modified = modified;
+        self
+    }
+
     /// Returns the filename (last component) of the archive path
     pub fn file_name(&self) -> &str {
         Path::new(&self.archive_path)
@@ -77,10 +208,134 @@ impl ArchiveFile {
         self.archive_path.ends_with(".json")
     }
 
-    /// Checks if this is a supplemental metadata file
+    /// Checks if this is a supplemental metadata file by its name alone. Sidecars cut inside
+    /// the media name also need the media, see [`Takeout::is_supplemental_metadata`].
     pub fn is_supplemental_metadata(&self) -> bool {
         is_supplemental_metadata_path(&self.archive_path)
     }
+
+    /// Checks if this is the metadata file of an album
+    pub fn is_album_metadata(&self) -> bool {
+        is_album_metadata_path(&self.archive_path)
+    }
+}
+
+/// Describes which naming rule paired a media file with its sidecar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    /// The sidecar is named after the media file, possibly with a truncated suffix
+    Exact,
+    /// The media is a numbered duplicate like "photo(1).jpg"
+    DuplicateCounter,
+    /// The media is an edited copy like "photo-edited.jpg" sharing the original's sidecar
+    Edited,
+    /// The sidecar name was cut inside the media name and was confirmed by its title
+    Truncated,
+    /// The sidecar's name is unrelated, but its title is the media name
+    Title,
+    /// The media is the video half of a Live Photo or Motion Photo sharing the still's sidecar
+    Companion,
+}
+
+/// A sidecar found for a media file, along with how it was found
+#[derive(Debug, Clone, Copy)]
+pub struct MetadataMatch<'a> {
+    pub file: &'a ArchiveFile,
+    pub kind: MatchKind,
+}
+
+/// Result of looking up a media file's sidecar, by name and then by the title recorded inside it
+#[derive(Debug, Clone)]
+pub enum MetadataLookup<'a> {
+    /// A sidecar is named after the media file, or exactly one unclaimed sidecar has the media
+    /// name as its title
+    Found(MetadataMatch<'a>),
+    /// Several unclaimed sidecars have the media name as their title
+    Ambiguous(Vec<&'a ArchiveFile>),
+    /// No sidecar was found
+    NotFound,
+}
+
+/// The sidecars of a takeout not claimed by any media file by name, keyed by their directory
+/// and the title recorded inside them
+struct TitleIndex<'a> {
+    sidecars: HashMap<(String, String), Vec<&'a ArchiveFile>>,
+}
+
+impl<'a> TitleIndex<'a> {
+    fn new(takeout: &'a Takeout, titles: &SidecarTitles, claimed: &HashSet<&str>) -> Self {
+        let mut sidecars: HashMap<_, Vec<_>> = HashMap::new();
+        for sidecar in takeout.supplemental_metadata_files() {
+            if claimed.contains(sidecar.archive_path.as_str()) {
+                continue;
+            }
+            if let Some(title) = titles.get(&sidecar.archive_path) {
+                let (dir, _) = split_file_name(&sidecar.archive_path);
+                sidecars
+                    .entry((dir.to_string(), title.clone()))
+                    .or_default()
+                    .push(sidecar);
+            }
+        }
+        TitleIndex { sidecars }
+    }
+
+    /// Finds the sidecar of a media file by the title recorded inside the sidecars of its
+    /// directory, for when no naming rule applies. Numbered duplicates like "photo(1).jpg"
+    /// fall back to the original name, since Takeout keeps it as the title.
+    fn find_title_metadata(&self, photo_path: &str) -> MetadataLookup<'a> {
+        let (dir, file_name) = split_file_name(photo_path);
+        let mut names = vec![file_name.to_string()];
+        if let Some((original_name, _)) = relocate_duplicate_counter(file_name) {
+            names.push(original_name);
+        }
+
+        for name in names {
+            let key = (dir.to_string(), name);
+            match self.sidecars.get(&key).map(Vec::as_slice) {
+                None | Some([]) => continue,
+                Some([file]) => {
+                    return MetadataLookup::Found(MetadataMatch {
+                        file,
+                        kind: MatchKind::Title,
+                    });
+                }
+                Some(candidates) => return MetadataLookup::Ambiguous(candidates.to_vec()),
+            }
+        }
+
+        MetadataLookup::NotFound
+    }
+}
+
+/// Pairs every sidecar found by its title with a single media file, so no sidecar is applied
+/// to several. Media named after the title wins over numbered duplicates falling back to it,
+/// then the first media by path. Returns the media path of each sidecar's owner, keyed by
+/// sidecar path.
+pub fn claim_title_metadata(
+    lookups: &HashMap<String, MetadataLookup>,
+    titles: &SidecarTitles,
+) -> HashMap<String, String> {
+    let mut matches = Vec::new();
+    for (path, lookup) in lookups {
+        if let MetadataLookup::Found(found) = lookup
+            && found.kind == MatchKind::Title
+        {
+            let sidecar = &found.file.archive_path;
+            let (_, file_name) = split_file_name(path);
+            let named_after_title = titles.get(sidecar).is_some_and(|t| t == file_name);
+            matches.push((!named_after_title, path, sidecar));
+        }
+    }
+    matches.sort_unstable();
+
+    let mut owners = HashMap::new();
+    for (_, path, sidecar) in matches {
+        owners
+            .entry(sidecar.clone())
+            .or_insert_with(|| path.clone());
+    }
+    owners
 }
 
 /// Error type for Takeout operations
@@ -119,14 +374,30 @@ impl std::fmt::Display for TakeoutError {
 
 impl std::error::Error for TakeoutError {}
 
+impl From<SourceError> for TakeoutError {
+    fn from(e: SourceError) -> Self {
+        TakeoutError::Other(e.to_string())
+    }
+}
+
 /// Represents a complete Google Takeout, potentially spanning multiple archive files.
 /// Files are indexed by their archive path for fast lookup.
 #[derive(Debug)]
 pub struct Takeout {
     /// All files in the takeout, keyed by their archive path
     files: HashMap<String, ArchiveFile>,
-    /// List of source archive paths that make up this takeout
-    source_archives: Vec<PathBuf>,
+    /// Archive paths of supplemental metadata files, and of JSON files whose name may have been
+    /// truncated, keyed by their directory (with trailing slash)
+    sidecars_by_directory: HashMap<String, Vec<String>>,
+    /// Archive paths of files other than JSON, keyed by their directory (with trailing slash)
+    media_by_directory: HashMap<String, Vec<String>>,
+    /// Archive paths of JSON files whose name is the start of the sidecar name of a media file
+    /// in the same directory, as Takeout left it after cutting it short
+    truncated_sidecars: HashSet<String>,
+    /// The sources (archives or directories) that make up this takeout
+    sources: Vec<Box<dyn ArchiveSource>>,
+    /// Suffixes marking edited media, see [`DEFAULT_EDITED_SUFFIXES`]
+    edited_suffixes: Vec<String>,
 }
 
 impl Takeout {
@@ -134,17 +405,35 @@ impl Takeout {
     pub fn new() -> Self {
         Self {
             files: HashMap::new(),
-            source_archives: Vec::new(),
+            sidecars_by_directory: HashMap::new(),
+            media_by_directory: HashMap::new(),
+            truncated_sidecars: HashSet::new(),
+            sources: Vec::new(),
+            edited_suffixes: DEFAULT_EDITED_SUFFIXES.iter().map(|s| s.to_string()).collect(),
         }
     }
 
-    /// Adds a source archive to the list of archives in this takeout
-    pub fn add_source_archive(&mut self, path: PathBuf) {
-        if !self.source_archives.contains(&path) {
-            self.source_archives.push(path);
+    /// Replaces the suffixes used to recognise edited media
+    pub fn set_edited_suffixes(&mut self, suffixes: Vec<String>) {
+        self.edited_suffixes = suffixes;
+    }
+
+    /// Adds a source to the list of sources in this takeout.
+    /// Sources are identified by their path, so adding the same path twice is a no-op.
+    pub fn add_source(&mut self, source: Box<dyn ArchiveSource>) {
+        if self.source(source.path()).is_none() {
+            self.sources.push(source);
         }
     }
 
+    /// Gets a source by its path
+    pub fn source(&self, path: &Path) -> Option<&dyn ArchiveSource> {
+        self.sources
+            .iter()
+            .find(|s| s.path() == path)
+            .map(|s| s.as_ref())
+    }
+
     /// Inserts an ArchiveFile into the Takeout.
     /// Returns an error if a file with the same archive path already exists.
     pub fn insert(&mut self, file: ArchiveFile) -> Result<(), TakeoutError> {
@@ -156,6 +445,42 @@ impl Takeout {
             });
         }
 
+        // Sidecars whose name was cut short are told apart by the media next to them, which
+        // may be inserted before or after them
+        let (dir, file_name) = split_file_name(&file.archive_path);
+        if file.is_supplemental_metadata() || is_truncated_sidecar_name(file_name) {
+            let truncated = !file.is_supplemental_metadata()
+                && self.media_by_directory.get(dir).into_iter().flatten().any(|media_path| {
+                    let (_, media_name) = split_file_name(media_path);
+                    is_truncated_sidecar_of(file_name, media_name)
+                });
+            if truncated {
+                self.truncated_sidecars.insert(file.archive_path.clone());
+            }
+            self.sidecars_by_directory
+                .entry(dir.to_string())
+                .or_default()
+                .push(file.archive_path.clone());
+        } else if !file.is_metadata() {
+            let truncated: Vec<_> = self
+                .sidecars_by_directory
+                .get(dir)
+                .into_iter()
+                .flatten()
+                .filter(|path| {
+                    let (_, sidecar_name) = split_file_name(path);
+                    !is_supplemental_metadata_path(path)
+                        && is_truncated_sidecar_of(sidecar_name, file_name)
+                })
+                .cloned()
+                .collect();
+            self.truncated_sidecars.extend(truncated);
+            self.media_by_directory
+                .entry(dir.to_string())
+                .or_default()
+                .push(file.archive_path.clone());
+        }
+
         self.files.insert(file.archive_path.clone(), file);
         Ok(())
     }
@@ -182,12 +507,24 @@ impl Takeout {
 
     /// Returns an iterator over supplemental metadata files in the takeout
     pub fn supplemental_metadata_files(&self) -> impl Iterator<Item = &ArchiveFile> {
-        self.files.values().filter(|f| f.is_supplemental_metadata())
+        self.files.values().filter(|f| self.is_supplemental_metadata(f))
+    }
+
+    /// Checks if a file is a supplemental metadata file. Besides sidecars named after their
+    /// media, this includes long JSON files whose name is the start of the sidecar name of a
+    /// media file in the same directory, as Takeout left it after cutting it short.
+    pub fn is_supplemental_metadata(&self, file: &ArchiveFile) -> bool {
+        file.is_supplemental_metadata() || self.truncated_sidecars.contains(&file.archive_path)
     }
 
-    /// Returns the list of source archives
-    pub fn source_archives(&self) -> &[PathBuf] {
-        &self.source_archives
+    /// Returns an iterator over the metadata files of albums in the takeout
+    pub fn album_metadata_files(&self) -> impl Iterator<Item = &ArchiveFile> {
+        self.files.values().filter(|f| f.is_album_metadata())
+    }
+
+    /// Returns the sources that make up this takeout
+    pub fn sources(&self) -> impl Iterator<Item = &dyn ArchiveSource> {
+        self.sources.iter().map(|s| s.as_ref())
     }
 
     /// Finds all files in a specific directory path within the archive
@@ -205,15 +542,182 @@ impl Takeout {
     }
 
     /// Finds a potential metadata file for a given photo file.
-    /// Google Takeout uses the pattern: "photo.jpg" -> "photo.jpg.json" or
-    /// "photo.jpg" -> "photo.jpg.supplemental-metadata.json"
+    /// See [`Takeout::find_metadata_match`] for the naming rules that are tried.
     pub fn find_metadata_for(&self, photo_path: &str) -> Option<&ArchiveFile> {
+        self.find_metadata_match(photo_path).map(|m| m.file)
+    }
+
+    /// Finds a potential metadata file for a given photo file, reporting which rule matched.
+    /// Google Takeout uses the pattern: "photo.jpg" -> "photo.jpg.json" or
+    /// "photo.jpg" -> "photo.jpg.supplemental-metadata.json".
+    /// Numbered duplicates have their counter moved behind the suffix:
+    /// "photo(1).jpg" -> "photo.jpg.supplemental-metadata(1).json".
+    /// Edited copies share the original's sidecar: "photo-edited.jpg" -> "photo.jpg.supplemental-metadata.json"
+    /// and so do the videos of Live Photos and Motion Photos, see [`Takeout::find_companion_still`].
+    pub fn find_metadata_match(&self, photo_path: &str) -> Option<MetadataMatch<'_>> {
+        if let Some(found) = self.find_metadata_by_name(photo_path) {
+            return Some(found);
+        }
+
+        let (dir, file_name) = split_file_name(photo_path);
+        let (stem, extension) = split_extension(file_name);
+        for suffix in &self.edited_suffixes {
+            if let Some(original_stem) = stem.strip_suffix(suffix.as_str())
+                && !original_stem.is_empty()
+            {
+                let original_path = format!("{}{}{}", dir, original_stem, extension);
+                if let Some(found) = self.find_metadata_by_name(&original_path) {
+                    return Some(MetadataMatch {
+                        file: found.file,
+                        kind: MatchKind::Edited,
+                    });
+                }
+            }
+        }
+
+        if let Some(still) = self.find_companion_still(photo_path)
+            && let Some(found) = self.find_metadata_by_name(&still.archive_path)
+        {
+            return Some(MetadataMatch {
+                file: found.file,
+                kind: MatchKind::Companion,
+            });
+        }
+
+        None
+    }
+
+    /// Finds the still image of a Live Photo or Motion Photo, given its video half.
+    /// The still is named like the video with an image extension ("IMG_1234.MOV" ->
+    /// "IMG_1234.HEIC"), or for Pixel Motion Photos with ".jpg" appended ("PXL_1234.MP" ->
+    /// "PXL_1234.MP.jpg").
+    pub fn find_companion_still(&self, video_path: &str) -> Option<&ArchiveFile> {
+        let lower = video_path.to_lowercase();
+        if !COMPANION_VIDEO_EXTENSIONS.iter().any(|ext| lower.ends_with(ext)) {
+            return None;
+        }
+
+        let (stem, _) = split_extension(video_path);
+        for base in [stem, video_path] {
+            for extension in COMPANION_STILL_EXTENSIONS {
+                for candidate in [
+                    format!("{}{}", base, extension),
+                    format!("{}{}", base, extension.to_uppercase()),
+                ] {
+                    if let Some(still) = self.files.get(&candidate) {
+                        return Some(still);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Finds the sidecar of a media file whose sidecar name was truncated inside the media name,
+    /// e.g. "a_very_long_camera_generated_filename_20190101_123456789.jpg" ->
+    /// "a_very_long_camera_generated_filename_20190101_1.json".
+    /// Sidecars in the same directory whose name is a prefix of the full sidecar name are
+    /// candidates, and the one whose title is the media name wins. Returns None if no
+    /// candidate or more than one candidate has a matching title.
+    pub fn find_truncated_metadata(
+        &self,
+        photo_path: &str,
+        titles: &SidecarTitles,
+    ) -> Option<MetadataMatch<'_>> {
+        let (dir, file_name) = split_file_name(photo_path);
+        let name = match relocate_duplicate_counter(file_name) {
+            Some((original_name, _)) => original_name,
+            None => file_name.to_string(),
+        };
+
+        let candidates: Vec<_> = self
+            .sidecars_in_directory(dir)
+            .filter(|sidecar| is_truncated_sidecar_of(sidecar.file_name(), file_name))
+            .filter(|sidecar| titles.get(&sidecar.archive_path) == Some(&name))
+            .collect();
+
+        match candidates.as_slice() {
+            [file] => Some(MetadataMatch {
+                file,
+                kind: MatchKind::Truncated,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Looks up the sidecar of every media file once: by the naming rules, then by the title
+    /// recorded inside the sidecars of its directory. Sidecars found by name belong to their
+    /// media and are never found by title. Returns the lookup of each media path.
+    pub fn look_up_metadata<'p>(
+        &self,
+        media_paths: impl IntoIterator<Item = &'p str>,
+        titles: &SidecarTitles,
+    ) -> HashMap<String, MetadataLookup<'_>> {
+        let named: Vec<_> = media_paths
+            .into_iter()
+            .map(|path| (path, self.find_metadata_match(path)))
+            .collect();
+        let claimed: HashSet<&str> = named
+            .iter()
+            .filter_map(|(_, found)| found.as_ref())
+            .map(|found| found.file.archive_path.as_str())
+            .collect();
+        let index = TitleIndex::new(self, titles, &claimed);
+
+        named
+            .into_iter()
+            .map(|(path, found)| {
+                let lookup = match found.or_else(|| self.find_truncated_metadata(path, titles)) {
+                    Some(found) => MetadataLookup::Found(found),
+                    None => index.find_title_metadata(path),
+                };
+                (path.to_string(), lookup)
+            })
+            .collect()
+    }
+
+    /// Returns the supplemental metadata files directly inside a directory
+    fn sidecars_in_directory(&self, dir: &str) -> impl Iterator<Item = &ArchiveFile> {
+        self.sidecars_by_directory
+            .get(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|path| self.files.get(path))
+            .filter(|file| self.is_supplemental_metadata(file))
+    }
+
+    /// Tries the sidecar names Takeout derives directly from the media name
+    fn find_metadata_by_name(&self, photo_path: &str) -> Option<MetadataMatch<'_>> {
         for suffix in SUPPLEMENTAL_SUFFIXES {
             let candidate = format!("{}{}json", photo_path, suffix);
             if let Some(file) = self.files.get(&candidate) {
-                return Some(file);
+                return Some(MetadataMatch {
+                    file,
+                    kind: MatchKind::Exact,
+                });
+            }
+        }
+
+        let (dir, file_name) = split_file_name(photo_path);
+        if let Some((original_name, counter)) = relocate_duplicate_counter(file_name) {
+            for suffix in SUPPLEMENTAL_SUFFIXES {
+                let candidate = format!(
+                    "{}{}{}{}.json",
+                    dir,
+                    original_name,
+                    suffix.trim_end_matches('.'),
+                    counter
+                );
+                if let Some(file) = self.files.get(&candidate) {
+                    return Some(MetadataMatch {
+                        file,
+                        kind: MatchKind::DuplicateCounter,
+                    });
+                }
             }
         }
+
         None
     }
 }
@@ -316,4 +820,437 @@ mod tests {
         assert!(found.is_some());
         assert!(found.unwrap().is_supplemental_metadata());
     }
+
+    fn insert_file(takeout: &mut Takeout, path: &str) {
+        let index = takeout.len();
+        takeout
+            .insert(ArchiveFile::new(
+                path.to_string(),
+                PathBuf::from("archive1.zip"),
+                index,
+                256,
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_find_duplicate_counter_metadata() {
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, "Takeout/Google Photos/Album/IMG_1234.jpg");
+        insert_file(&mut takeout, "Takeout/Google Photos/Album/IMG_1234(1).jpg");
+        insert_file(&mut takeout, "Takeout/Google Photos/Album/IMG_1234.jpg.supplemental-metadata.json");
+        insert_file(&mut takeout, "Takeout/Google Photos/Album/IMG_1234.jpg.supplemental-metadata(1).json");
+
+        let original = takeout
+            .find_metadata_for("Takeout/Google Photos/Album/IMG_1234.jpg")
+            .unwrap();
+        assert_eq!(
+            original.archive_path,
+            "Takeout/Google Photos/Album/IMG_1234.jpg.supplemental-metadata.json"
+        );
+
+        let duplicate = takeout
+            .find_metadata_for("Takeout/Google Photos/Album/IMG_1234(1).jpg")
+            .unwrap();
+        assert_eq!(
+            duplicate.archive_path,
+            "Takeout/Google Photos/Album/IMG_1234.jpg.supplemental-metadata(1).json"
+        );
+    }
+
+    #[test]
+    fn test_find_truncated_duplicate_counter_metadata() {
+        let mut takeout = Takeout::new();
+        for suffix in SUPPLEMENTAL_SUFFIXES {
+            let sidecar = format!(
+                "Takeout/Google Photos/IMG_1234.jpg{}(2).json",
+                suffix.trim_end_matches('.')
+            );
+            let mut takeout_for_suffix = Takeout::new();
+            insert_file(&mut takeout_for_suffix, &sidecar);
+
+            let found = takeout_for_suffix
+                .find_metadata_for("Takeout/Google Photos/IMG_1234(2).jpg")
+                .unwrap();
+            assert_eq!(found.archive_path, sidecar);
+            assert!(found.is_supplemental_metadata());
+        }
+
+        // The counter must not leak onto unrelated media
+        insert_file(&mut takeout, "Takeout/Google Photos/IMG_1234.jpg.supplemental-met(1).json");
+        assert!(takeout.find_metadata_for("Takeout/Google Photos/IMG_1234.jpg").is_none());
+        assert!(takeout.find_metadata_for("Takeout/Google Photos/IMG_1234(2).jpg").is_none());
+    }
+
+    #[test]
+    fn test_split_duplicate_counter() {
+        assert_eq!(split_duplicate_counter("IMG_1234(1)"), ("IMG_1234", Some("(1)")));
+        assert_eq!(split_duplicate_counter("IMG_1234(12)"), ("IMG_1234", Some("(12)")));
+        assert_eq!(split_duplicate_counter("Holiday (Rome)"), ("Holiday (Rome)", None));
+        assert_eq!(split_duplicate_counter("IMG_1234()"), ("IMG_1234()", None));
+        assert_eq!(
+            relocate_duplicate_counter("IMG_1234(1).jpg"),
+            Some(("IMG_1234.jpg".to_string(), "(1)"))
+        );
+        assert_eq!(relocate_duplicate_counter("IMG_1234.jpg"), None);
+    }
+
+    #[test]
+    fn test_is_album_metadata_path() {
+        assert!(is_album_metadata_path("Takeout/Google Photos/Album/metadata.json"));
+        assert!(is_album_metadata_path("Takeout/Google Fotos/Album/Metadaten.json"));
+        assert!(!is_album_metadata_path("Takeout/Google Photos/Album/metadata(1).json"));
+        assert!(!is_album_metadata_path(
+            "Takeout/Google Photos/Album/metadata.json.supplemental-metadata.json"
+        ));
+
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, "Takeout/Google Photos/Album/metadata.json");
+        insert_file(&mut takeout, "Takeout/Google Photos/Album/IMG_1234.jpg.supplemental-metadata.json");
+        assert_eq!(takeout.album_metadata_files().count(), 1);
+        assert_eq!(takeout.supplemental_metadata_files().count(), 1);
+    }
+
+    #[test]
+    fn test_find_edited_metadata() {
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, "Takeout/Google Photos/IMG_1234.jpg");
+        insert_file(&mut takeout, "Takeout/Google Photos/IMG_1234-edited.jpg");
+        insert_file(&mut takeout, "Takeout/Google Photos/IMG_1234-modifié.jpg");
+        insert_file(&mut takeout, "Takeout/Google Photos/IMG_1234.jpg.supplemental-metadata.json");
+
+        let original = takeout
+            .find_metadata_match("Takeout/Google Photos/IMG_1234.jpg")
+            .unwrap();
+        assert_eq!(original.kind, MatchKind::Exact);
+
+        for edited in [
+            "Takeout/Google Photos/IMG_1234-edited.jpg",
+            "Takeout/Google Photos/IMG_1234-modifié.jpg",
+        ] {
+            let found = takeout.find_metadata_match(edited).unwrap();
+            assert_eq!(found.kind, MatchKind::Edited);
+            assert_eq!(
+                found.file.archive_path,
+                "Takeout/Google Photos/IMG_1234.jpg.supplemental-metadata.json"
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_edited_metadata_custom_suffixes() {
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, "Takeout/Google Photos/IMG_1234.jpg.supplemental-met.json");
+        takeout.set_edited_suffixes(vec!["-retouched".to_string()]);
+
+        assert!(takeout.find_metadata_for("Takeout/Google Photos/IMG_1234-edited.jpg").is_none());
+        let found = takeout
+            .find_metadata_match("Takeout/Google Photos/IMG_1234-retouched.jpg")
+            .unwrap();
+        assert_eq!(found.kind, MatchKind::Edited);
+    }
+
+    #[test]
+    fn test_find_edited_duplicate_metadata() {
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, "Takeout/Google Photos/IMG_1234.jpg.supplemental-metadata(1).json");
+
+        let found = takeout
+            .find_metadata_match("Takeout/Google Photos/IMG_1234(1)-edited.jpg")
+            .unwrap();
+        assert_eq!(found.kind, MatchKind::Edited);
+    }
+
+    #[test]
+    fn test_find_truncated_media_name_metadata() {
+        let dir = "Takeout/Google Photos/Album/";
+        let photo = format!(
+            "{}a_very_long_camera_generated_filename_20190101_123456789.jpg",
+            dir
+        );
+        let other = format!(
+            "{}a_very_long_camera_generated_filename_20190101_123499999.jpg",
+            dir
+        );
+        let sidecar = format!(
+            "{}a_very_long_camera_generated_filename_20190101_12.json",
+            dir
+        );
+        let other_sidecar = format!(
+            "{}a_very_long_camera_generated_filename_20190101_1(1).json",
+            dir
+        );
+
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, &photo);
+        insert_file(&mut takeout, &other);
+        insert_file(&mut takeout, &sidecar);
+        insert_file(&mut takeout, &other_sidecar);
+        assert!(takeout.is_supplemental_metadata(takeout.get(&sidecar).unwrap()));
+
+        let mut titles = SidecarTitles::new();
+        titles.insert(
+            sidecar.clone(),
+            "a_very_long_camera_generated_filename_20190101_123456789.jpg".to_string(),
+        );
+        titles.insert(
+            other_sidecar.clone(),
+            "a_very_long_camera_generated_filename_20190101_123499999.jpg".to_string(),
+        );
+
+        // The path-based rules cannot find a sidecar cut inside the media name
+        assert!(takeout.find_metadata_for(&photo).is_none());
+
+        let found = takeout.find_truncated_metadata(&photo, &titles).unwrap();
+        assert_eq!(found.kind, MatchKind::Truncated);
+        assert_eq!(found.file.archive_path, sidecar);
+
+        // Both sidecars are prefixes of the other media's sidecar name, but neither
+        // has the right title and counter
+        assert!(takeout.find_truncated_metadata(&other, &titles).is_none());
+    }
+
+    #[test]
+    fn test_truncated_sidecar_inserted_before_media() {
+        let dir = "Takeout/Google Photos/Album/";
+        let photo = format!(
+            "{}a_very_long_camera_generated_filename_20190101_123456789.jpg",
+            dir
+        );
+        let sidecar = format!(
+            "{}a_very_long_camera_generated_filename_20190101_12.json",
+            dir
+        );
+
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, &sidecar);
+        assert!(!takeout.is_supplemental_metadata(takeout.get(&sidecar).unwrap()));
+        insert_file(&mut takeout, &photo);
+        assert!(takeout.is_supplemental_metadata(takeout.get(&sidecar).unwrap()));
+        assert_eq!(takeout.supplemental_metadata_files().count(), 1);
+    }
+
+    #[test]
+    fn test_find_truncated_metadata_ambiguous_title() {
+        let dir = "Takeout/Google Photos/Album/";
+        let photo = format!(
+            "{}a_very_long_camera_generated_filename_20190101_123456789.jpg",
+            dir
+        );
+        let first = format!(
+            "{}a_very_long_camera_generated_filename_20190101_12.json",
+            dir
+        );
+        let second = format!(
+            "{}a_very_long_camera_generated_filename_20190101_123.json",
+            dir
+        );
+
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, &photo);
+        insert_file(&mut takeout, &first);
+        insert_file(&mut takeout, &second);
+
+        let title = "a_very_long_camera_generated_filename_20190101_123456789.jpg".to_string();
+        let mut titles = SidecarTitles::new();
+        titles.insert(first, title.clone());
+        titles.insert(second, title);
+
+        assert!(takeout.find_truncated_metadata(&photo, &titles).is_none());
+    }
+
+    #[test]
+    fn test_long_json_without_media_is_not_sidecar() {
+        let dir = "Takeout/Google Photos/Album/";
+        let photo = format!("{}a_very_long_camera_generated_filename_20190101_1.jpg", dir);
+        let unrelated = format!("{}an_export_of_something_else_with_a_long_name_2019.json", dir);
+        let counted = format!("{}a_very_long_camera_generated_filename_2019(1).json", dir);
+
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, &photo);
+        insert_file(&mut takeout, &unrelated);
+        insert_file(&mut takeout, &counted);
+
+        // Neither is the start of "<media name>.supplemental-metadata" with the same counter
+        assert!(!takeout.is_supplemental_metadata(takeout.get(&unrelated).unwrap()));
+        assert!(!takeout.is_supplemental_metadata(takeout.get(&counted).unwrap()));
+        assert_eq!(takeout.supplemental_metadata_files().count(), 0);
+
+        let mut titles = SidecarTitles::new();
+        titles.insert(
+            unrelated,
+            "a_very_long_camera_generated_filename_20190101_1.jpg".to_string(),
+        );
+        assert!(takeout.find_truncated_metadata(&photo, &titles).is_none());
+        assert!(matches!(
+            TitleIndex::new(&takeout, &titles, &HashSet::new()).find_title_metadata(&photo),
+            MetadataLookup::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_find_title_metadata() {
+        let dir = "Takeout/Google Photos/Album/";
+        let mut takeout = Takeout::new();
+        for name in ["IMG_1234.jpg", "IMG_1234(1).jpg", "renamed.jpg"] {
+            insert_file(&mut takeout, &format!("{}{}", dir, name));
+        }
+        insert_file(&mut takeout, &format!("{}IMG_1234.jpg.supplemental-metadata.json", dir));
+        insert_file(&mut takeout, &format!("{}original.jpg.supplemental-metadata.json", dir));
+        insert_file(&mut takeout, &format!("{}other.jpg.supplemental-metadata.json", dir));
+
+        let mut titles = SidecarTitles::new();
+        for (sidecar, title) in [
+            ("IMG_1234.jpg.supplemental-metadata.json", "IMG_1234.jpg"),
+            ("original.jpg.supplemental-metadata.json", "renamed.jpg"),
+            ("other.jpg.supplemental-metadata.json", "IMG_1234.jpg"),
+        ] {
+            titles.insert(format!("{}{}", dir, sidecar), title.to_string());
+        }
+        let claimed_sidecar = format!("{}IMG_1234.jpg.supplemental-metadata.json", dir);
+        let index = TitleIndex::new(&takeout, &titles, &HashSet::from([claimed_sidecar.as_str()]));
+
+        let photo = format!("{}renamed.jpg", dir);
+        assert!(takeout.find_metadata_for(&photo).is_none());
+        let MetadataLookup::Found(found) = index.find_title_metadata(&photo) else {
+            panic!("expected a title match for {}", photo);
+        };
+        assert_eq!(found.kind, MatchKind::Title);
+        assert_eq!(
+            found.file.archive_path,
+            format!("{}original.jpg.supplemental-metadata.json", dir)
+        );
+
+        // The duplicate falls back to the original's title, skipping the claimed sidecar
+        let duplicate = format!("{}IMG_1234(1).jpg", dir);
+        let MetadataLookup::Found(found) = index.find_title_metadata(&duplicate) else {
+            panic!("expected a title match for {}", duplicate);
+        };
+        assert_eq!(
+            found.file.archive_path,
+            format!("{}other.jpg.supplemental-metadata.json", dir)
+        );
+
+        assert!(matches!(
+            index.find_title_metadata(&format!("{}missing.jpg", dir)),
+            MetadataLookup::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_find_title_metadata_ambiguous() {
+        let dir = "Takeout/Google Photos/Album/";
+        let mut takeout = Takeout::new();
+        insert_file(&mut takeout, &format!("{}photo.jpg", dir));
+        insert_file(&mut takeout, &format!("{}a.jpg.supplemental-metadata.json", dir));
+        insert_file(&mut takeout, &format!("{}b.jpg.supplemental-metadata.json", dir));
+        // Sidecars in other directories are never candidates
+        insert_file(&mut takeout, "Takeout/Google Photos/Other/c.jpg.supplemental-metadata.json");
+
+        let mut titles = SidecarTitles::new();
+        for sidecar in [
+            format!("{}a.jpg.supplemental-metadata.json", dir),
+            format!("{}b.jpg.supplemental-metadata.json", dir),
+            "Takeout/Google Photos/Other/c.jpg.supplemental-metadata.json".to_string(),
+        ] {
+            titles.insert(sidecar, "photo.jpg".to_string());
+        }
+
+        let photo = format!("{}photo.jpg", dir);
+        match TitleIndex::new(&takeout, &titles, &HashSet::new()).find_title_metadata(&photo) {
+            MetadataLookup::Ambiguous(candidates) => assert_eq!(candidates.len(), 2),
+            other => panic!("expected an ambiguous match, got {:?}", other),
+        }
+
+        let claimed = format!("{}a.jpg.supplemental-metadata.json", dir);
+        let index = TitleIndex::new(&takeout, &titles, &HashSet::from([claimed.as_str()]));
+        assert!(matches!(
+            index.find_title_metadata(&photo),
+            MetadataLookup::Found(_)
+        ));
+    }
+
+    #[test]
+    fn test_claim_title_metadata() {
+        let dir = "Takeout/Google Photos/Album/";
+        let mut takeout = Takeout::new();
+        for name in ["renamed.jpg", "renamed(1).jpg", "IMG_1234.jpg"] {
+            insert_file(&mut takeout, &format!("{}{}", dir, name));
+        }
+        insert_file(&mut takeout, &format!("{}original.jpg.supplemental-metadata.json", dir));
+        insert_file(&mut takeout, &format!("{}IMG_1234.jpg.supplemental-metadata.json", dir));
+
+        let mut titles = SidecarTitles::new();
+        titles.insert(
+            format!("{}original.jpg.supplemental-metadata.json", dir),
+            "renamed.jpg".to_string(),
+        );
+        titles.insert(
+            format!("{}IMG_1234.jpg.supplemental-metadata.json", dir),
+            "IMG_1234.jpg".to_string(),
+        );
+
+        // The duplicate falls back to the same title, but the sidecar goes to the original only
+        let media = [
+            format!("{}renamed(1).jpg", dir),
+            format!("{}renamed.jpg", dir),
+            format!("{}IMG_1234.jpg", dir),
+        ];
+        let lookups = takeout.look_up_metadata(media.iter().map(|path| path.as_str()), &titles);
+        let owners = claim_title_metadata(&lookups, &titles);
+        assert_eq!(
+            owners,
+            HashMap::from([(
+                format!("{}original.jpg.supplemental-metadata.json", dir),
+                format!("{}renamed.jpg", dir)
+            )])
+        );
+    }
+
+    #[test]
+    fn test_find_companion_video_metadata() {
+        let dir = "Takeout/Google Photos/Album/";
+        let mut takeout = Takeout::new();
+        for name in [
+            "IMG_1234.HEIC",
+            "IMG_1234.MOV",
+            "IMG_1234.HEIC.supplemental-metadata.json",
+            "PXL_20250415_160315147.MP.jpg",
+            "PXL_20250415_160315147.MP",
+            "PXL_20250415_160315147.MP.jpg.supplemental-met.json",
+            "VID_0001.mp4",
+            "clip.mov",
+            "clip.mov.supplemental-metadata.json",
+            "clip.jpg",
+            "clip.jpg.supplemental-metadata.json",
+        ] {
+            insert_file(&mut takeout, &format!("{}{}", dir, name));
+        }
+
+        let found = takeout
+            .find_metadata_match(&format!("{}IMG_1234.MOV", dir))
+            .unwrap();
+        assert_eq!(found.kind, MatchKind::Companion);
+        assert_eq!(
+            found.file.archive_path,
+            format!("{}IMG_1234.HEIC.supplemental-metadata.json", dir)
+        );
+
+        let found = takeout
+            .find_metadata_match(&format!("{}PXL_20250415_160315147.MP", dir))
+            .unwrap();
+        assert_eq!(found.kind, MatchKind::Companion);
+        assert_eq!(
+            found.file.archive_path,
+            format!("{}PXL_20250415_160315147.MP.jpg.supplemental-met.json", dir)
+        );
+
+        // A video without a still stays unmatched, one with its own sidecar keeps it
+        assert!(takeout.find_metadata_for(&format!("{}VID_0001.mp4", dir)).is_none());
+        let found = takeout.find_metadata_match(&format!("{}clip.mov", dir)).unwrap();
+        assert_eq!(found.kind, MatchKind::Exact);
+
+        // Stills are never companions of anything
+        assert!(takeout.find_companion_still(&format!("{}IMG_1234.HEIC", dir)).is_none());
+    }
 }
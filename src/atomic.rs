@@ -0,0 +1,150 @@
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+/// Ends the name of files being written, which a crash can leave behind
+pub const TEMP_SUFFIX: &str = ".takeout-fixer-tmp";
+
+/// Returns a path next to `path` that nothing else writes to, so it can be renamed over `path`
+/// without crossing file systems
+pub fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(
+        ".{}.{}-{}{}",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        TEMP_SUFFIX
+    ))
+}
+
+/// Syncs a directory to disk, so entries renamed into it survive a power loss. Only Unix can
+/// open directories for this, elsewhere the rename is left to the file system.
+fn sync_directory(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Writes `data` to `path`, dated `modified` if given. The data goes to a temporary file that
+/// is synced to disk and then renamed to `path`, so `path` is either left as it was or has
+/// the complete data, never a part of it.
+pub fn write_atomically(path: &Path, data: &[u8], modified: Option<SystemTime>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp = temp_path(path);
+    let result = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            if let Some(time) = modified {
+                file.set_times(FileTimes::new().set_accessed(time).set_modified(time))?;
+            }
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => sync_directory(parent),
+        _ => sync_directory(Path::new(".")),
+    }
+}
+
+/// Removes the temporary files that an interrupted run left behind anywhere below `dir`,
+/// returning how many there were
+pub fn remove_temp_files(dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                stack.push(entry.path());
+                continue;
+            }
+
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if file_type.is_file() && name.starts_with('.') && name.ends_with(TEMP_SUFFIX) {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_temp_path() {
+        let path = Path::new("out/Trip/a.jpg");
+        let temp = temp_path(path);
+        assert_eq!(temp.parent(), path.parent());
+        let name = temp.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with(".a.jpg."));
+        assert!(name.ends_with(TEMP_SUFFIX));
+        assert_ne!(temp_path(path), temp);
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("takeout-fixer-atomic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("Trip/a.jpg");
+
+        write_atomically(&path, b"image", None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"image");
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_563_032_119);
+        write_atomically(&path, b"new image", Some(time)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new image");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), time);
+
+        // Only the file itself is left in its folder
+        let names: Vec<_> = fs::read_dir(dir.join("Trip"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["a.jpg"]);
+
+        // A failed write leaves no temporary file behind
+        assert!(write_atomically(&dir.join("Trip/a.jpg/b.jpg"), b"image", None).is_err());
+        assert_eq!(fs::read_dir(dir.join("Trip")).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_temp_files() {
+        let dir = std::env::temp_dir().join(format!("takeout-fixer-temp-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("Trip/a.jpg");
+        write_atomically(&path, b"image", None).unwrap();
+        fs::write(temp_path(&path), b"ima").unwrap();
+        fs::write(temp_path(&dir.join("b.jpg")), b"").unwrap();
+        fs::write(dir.join(format!("notes{}", TEMP_SUFFIX)), b"").unwrap();
+
+        assert_eq!(remove_temp_files(&dir).unwrap(), 2);
+        assert!(path.exists());
+        assert!(dir.join(format!("notes{}", TEMP_SUFFIX)).exists());
+        assert_eq!(fs::read_dir(dir.join("Trip")).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
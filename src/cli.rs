@@ -1,3 +1,10 @@
+use crate::archive::DEFAULT_EDITED_SUFFIXES;
+use crate::collision::CollisionPolicy;
+use crate::dedup::DedupMode;
+use crate::layout::{Layout, DEFAULT_LAYOUT};
+use crate::metadata::{Preference, DEFAULT_FAVORITE_RATING};
+use crate::process::{ArchivedMode, MetadataMode, TrashedMode};
+use crate::timezone::Timezone;
 use clap::{Parser, Subcommand};
 use glob::glob;
 use std::path::PathBuf;
@@ -14,6 +21,11 @@ pub struct Cli {
     #[arg(short = 'n', long, action = clap::ArgAction::SetTrue)]
     pub dry_run: bool,
 
+    /// Continue an interrupted run in an existing output directory, skipping media its journal
+    /// records as written and still intact
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub resume: bool,
+
     /// Photo directory name inside the archive
     #[arg(short, long, default_value = "Google Photos")]
     pub photo_dir: String,
@@ -26,15 +38,116 @@ pub struct Cli {
     #[arg(long, action = clap::ArgAction::SetTrue)]
     pub no_progress: bool,
 
+    /// Keep the time of the run as the modification time of output files instead of
+    /// setting it to when the media was taken
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub no_file_times: bool,
+
+    /// Where to write metadata: into the media files, or into an XMP sidecar next to each
+    /// file (e.g. photo.jpg.xmp) leaving the media untouched
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = MetadataMode::Embed)]
+    pub metadata_mode: MetadataMode,
+
+    /// Timezone for media without location data, e.g. "Europe/Copenhagen" or "+02:00".
+    /// Media with a location use the timezone of that location. [default: UTC]
+    #[arg(long, value_name = "TZ")]
+    pub timezone: Option<Timezone>,
+
+    /// Which value wins when an image already has a date, location or description that the
+    /// sidecar also has
+    #[arg(long, value_enum, value_name = "SOURCE", default_value_t = Preference::Sidecar)]
+    pub prefer: Preference,
+
+    /// Overrides --prefer for the capture date
+    #[arg(long, value_enum, value_name = "SOURCE")]
+    pub prefer_date: Option<Preference>,
+
+    /// Overrides --prefer for the GPS location
+    #[arg(long, value_enum, value_name = "SOURCE")]
+    pub prefer_gps: Option<Preference>,
+
+    /// Overrides --prefer for the description
+    #[arg(long, value_enum, value_name = "SOURCE")]
+    pub prefer_description: Option<Preference>,
+
+    /// Star rating, from 1 to 5, given to media marked as a favorite in Google Photos
+    #[arg(long, value_name = "STARS", default_value_t = DEFAULT_FAVORITE_RATING, value_parser = clap::value_parser!(u8).range(1..=5))]
+    pub favorite_rating: u8,
+
+    /// Keyword to add to media marked as a favorite in Google Photos, e.g. "Favorite"
+    #[arg(long, value_name = "KEYWORD")]
+    pub favorite_keyword: Option<String>,
+
+    /// What to do with media in the trash in Google Photos: leave it out, include it, or write
+    /// it to a separate Trash folder
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = TrashedMode::Include)]
+    pub trashed: TrashedMode,
+
+    /// What to do with media archived in Google Photos: include it, or write it to a separate
+    /// Archive folder
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = ArchivedMode::Include)]
+    pub archived: ArchivedMode,
+
+    /// Add the title of its album as a keyword to each media file. Cannot be combined with
+    /// --dedup
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub album_keywords: bool,
+
+    /// Write media that appears in several folders (e.g. a year folder and an album) once, and
+    /// hardlink or relatively symlink it into the other folders, or list it in an album manifest
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = DedupMode::Off)]
+    pub dedup: DedupMode,
+
+    /// Where media is written below the output directory, using the placeholders {year},
+    /// {month}, {day}, {album} and {filename}, e.g. "{year}/{month}/{filename}". Dates are
+    /// taken from the sidecar, then EXIF, then the archive.
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_LAYOUT)]
+    pub layout: Layout,
+
+    /// What to do when several media files map to the same output path: number the later
+    /// file, skip it, count it as an error, or keep whichever file is larger
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = CollisionPolicy::Suffix)]
+    pub on_collision: CollisionPolicy,
+
+    /// Treat sidecars with fields this tool does not know about as errors
+    #[arg(long, action = clap::ArgAction::SetTrue, overrides_with = "lenient")]
+    pub strict: bool,
+
+    /// Ignore sidecar fields this tool does not know about, listing them in the summary (default)
+    #[arg(long, action = clap::ArgAction::SetTrue, overrides_with = "strict")]
+    pub lenient: bool,
+
+    /// Number of media files to process in parallel [default: number of CPUs]
+    #[arg(short, long, value_parser = validate_jobs)]
+    pub jobs: Option<usize>,
+
+    /// Suffix Google Photos adds to edited media, e.g. "-edited". Can be given multiple times
+    /// and replaces the built-in list of localized suffixes
+    #[arg(long = "edited-suffix", value_name = "SUFFIX", default_values_t = default_edited_suffixes())]
+    pub edited_suffixes: Vec<String>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+fn default_edited_suffixes() -> Vec<String> {
+    DEFAULT_EDITED_SUFFIXES.iter().map(|s| s.to_string()).collect()
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Fixes Google Takeout photo metadata issues
     Fix {
-        /// Paths to .zip or .tar.gz files, directories containing them, or glob patterns like *.zip
+        /// Paths to .zip or .tar.gz files, extracted Takeout directories, directories containing
+        /// either, or glob patterns like *.zip
+        #[arg(required = true, num_args = 1.., value_parser = validate_path)]
+        paths: Vec<PathBuf>,
+    },
+    /// Merges a newer takeout into the output of an earlier run, writing only media that is new
+    /// or whose sidecar changed
+    Update {
+        /// Paths to .zip or .tar.gz files, extracted Takeout directories, directories containing
+        /// either, or glob patterns like *.zip
         #[arg(required = true, num_args = 1.., value_parser = validate_path)]
         paths: Vec<PathBuf>,
     },
@@ -45,6 +158,24 @@ fn is_archive_file(path: &std::path::Path) -> bool {
     file_name.ends_with(".zip") || file_name.ends_with(".tar.gz")
 }
 
+/// Checks if a directory is an already-extracted Takeout archive,
+/// i.e. it contains a top-level "Takeout" folder
+fn is_extracted_takeout(path: &std::path::Path) -> bool {
+    path.is_dir() && path.join("Takeout").is_dir()
+}
+
+fn is_takeout_source(path: &std::path::Path) -> bool {
+    (path.is_file() && is_archive_file(path)) || is_extracted_takeout(path)
+}
+
+fn validate_jobs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("Number of jobs must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(format!("Invalid number of jobs '{}': {}", s, e)),
+    }
+}
+
 fn validate_path(s: &str) -> Result<PathBuf, String> {
     // Check if it looks like a glob pattern
     if s.contains('*') || s.contains('?') || s.contains('[') {
@@ -64,7 +195,7 @@ fn validate_path(s: &str) -> Result<PathBuf, String> {
     }
 
     if path.is_dir() {
-        // Directory is allowed - we'll scan it for archives
+        // Directory is allowed - it is either an extracted takeout or we'll scan it for archives
         return Ok(path);
     }
 
@@ -91,29 +222,32 @@ pub fn expand_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
             let matches: Vec<_> = glob(&path_str)
                 .map_err(|e| format!("Invalid glob pattern '{}': {}", path_str, e))?
                 .filter_map(|r| r.ok())
-                .filter(|p| is_archive_file(p))
+                .filter(|p| is_takeout_source(p))
                 .collect();
 
             if matches.is_empty() {
                 return Err(format!(
-                    "No .zip or .tar.gz files matched pattern: {}",
+                    "No .zip or .tar.gz files or extracted takeouts matched pattern: {}",
                     path_str
                 ));
             }
 
             files.extend(matches);
+        } else if is_extracted_takeout(path) {
+            // Already-extracted takeout, use the directory itself as a source
+            files.push(path.clone());
         } else if path.is_dir() {
-            // Scan directory for archive files
+            // Scan directory for archive files and extracted takeouts
             let dir_files: Vec<_> = std::fs::read_dir(path)
                 .map_err(|e| format!("Failed to read directory '{}': {}", path_str, e))?
                 .filter_map(|entry| entry.ok())
                 .map(|entry| entry.path())
-                .filter(|p| p.is_file() && is_archive_file(p))
+                .filter(|p| is_takeout_source(p))
                 .collect();
 
             if dir_files.is_empty() {
                 return Err(format!(
-                    "No .zip or .tar.gz files found in directory: {}",
+                    "No .zip or .tar.gz files or extracted takeouts found in directory: {}",
                     path_str
                 ));
             }
@@ -0,0 +1,298 @@
+use crate::sync::lock;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What to do when several media files would be written to the same output path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CollisionPolicy {
+    /// Write the later file with a number added to its name, e.g. "photo (1).jpg"
+    #[default]
+    Suffix,
+    /// Leave the later file out
+    Skip,
+    /// Count the later file as an error and leave it out
+    Error,
+    /// Keep whichever file is larger
+    KeepLarger,
+}
+
+/// How a collision was resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The later file was written to another path
+    Renamed(PathBuf),
+    /// The later file was left out
+    Skipped,
+    /// The later file was left out and counted as an error
+    Failed,
+    /// The later file was larger and is written instead of the earlier one
+    Replaced,
+}
+
+/// Two media files mapped to the same output path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    /// The path both files map to
+    pub output_path: PathBuf,
+    /// The archive path of the file that had the output path first
+    pub existing: String,
+    /// The archive path of the file that was mapped to it later
+    pub incoming: String,
+    pub resolution: Resolution,
+}
+
+impl std::fmt::Display for Collision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} and {}, ",
+            self.output_path.display(),
+            self.existing,
+            self.incoming
+        )?;
+        match &self.resolution {
+            Resolution::Renamed(path) => write!(f, "the latter written to {}", path.display()),
+            Resolution::Skipped => write!(f, "the latter skipped"),
+            Resolution::Failed => write!(f, "the latter failed"),
+            Resolution::Replaced => write!(f, "the larger latter kept"),
+        }
+    }
+}
+
+/// The outcome of claiming an output path
+#[derive(Debug)]
+pub struct Claim {
+    /// Where to write the file, or `None` if it must not be written
+    pub path: Option<PathBuf>,
+    /// The collision the claim ran into, if any
+    pub collision: Option<Collision>,
+}
+
+/// The media file an output path is claimed by
+struct Owner {
+    archive_path: String,
+    size: u64,
+    /// The path as claimed, which may differ in case from later claims
+    path: PathBuf,
+}
+
+/// The output paths media files are written to, all claimed before any is written so which
+/// file gets a path does not depend on the order they are processed in. Paths are compared
+/// ignoring case, as the output may be on a case-insensitive file system.
+#[derive(Default)]
+pub struct OutputClaims {
+    owners: Mutex<HashMap<String, Owner>>,
+}
+
+/// Returns the key a path is claimed under
+fn claim_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Returns `path` with a number added to its file name, e.g. `photo (1).jpg`
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{} ({}).{}", stem, number, extension.to_string_lossy()),
+        None => format!("{} ({})", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+impl OutputClaims {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Claims `path` for a media file of `size` bytes, resolving a collision with an earlier
+    /// claim according to `policy`
+    pub fn claim(
+        &self,
+        path: &Path,
+        archive_path: &str,
+        size: u64,
+        policy: CollisionPolicy,
+    ) -> Claim {
+        let mut owners = lock(&self.owners);
+        let new_owner = |path: &Path| Owner {
+            archive_path: archive_path.to_string(),
+            size,
+            path: path.to_path_buf(),
+        };
+
+        let key = claim_key(path);
+        let Some(existing) = owners.get_mut(&key) else {
+            owners.insert(key, new_owner(path));
+            return Claim {
+                path: Some(path.to_path_buf()),
+                collision: None,
+            };
+        };
+
+        // Resumed runs claim the paths of media written earlier before writing it again
+        if existing.archive_path == archive_path {
+            return Claim {
+                path: Some(existing.path.clone()),
+                collision: None,
+            };
+        }
+
+        let existing_path = existing.archive_path.clone();
+        let (claimed, resolution) = match policy {
+            CollisionPolicy::Suffix => {
+                let renamed = (1..)
+                    .map(|number| numbered_path(path, number))
+                    .find(|renamed| !owners.contains_key(&claim_key(renamed)))
+                    .expect("some numbered path is free");
+                owners.insert(claim_key(&renamed), new_owner(&renamed));
+                (Some(renamed.clone()), Resolution::Renamed(renamed))
+            }
+            CollisionPolicy::Skip => (None, Resolution::Skipped),
+            CollisionPolicy::Error => (None, Resolution::Failed),
+            CollisionPolicy::KeepLarger if size > existing.size => {
+                existing.archive_path = archive_path.to_string();
+                existing.size = size;
+                (Some(existing.path.clone()), Resolution::Replaced)
+            }
+            CollisionPolicy::KeepLarger => (None, Resolution::Skipped),
+        };
+
+        Claim {
+            path: claimed,
+            collision: Some(Collision {
+                output_path: path.to_path_buf(),
+                existing: existing_path,
+                incoming: archive_path.to_string(),
+                resolution,
+            }),
+        }
+    }
+
+    /// Returns the archive path of the file `path` is claimed by
+    pub fn owner(&self, path: &Path) -> Option<String> {
+        lock(&self.owners)
+            .get(&claim_key(path))
+            .map(|owner| owner.archive_path.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "out/2019/IMG_0001.jpg";
+
+    fn claim(
+        claims: &OutputClaims,
+        path: &str,
+        archive_path: &str,
+        size: u64,
+        policy: CollisionPolicy,
+    ) -> Claim {
+        claims.claim(Path::new(path), archive_path, size, policy)
+    }
+
+    #[test]
+    fn test_first_claim_has_no_collision() {
+        let claims = OutputClaims::new();
+        let first = claim(&claims, PATH, "a/IMG_0001.jpg", 10, CollisionPolicy::Error);
+        assert_eq!(first.path, Some(PathBuf::from(PATH)));
+        assert!(first.collision.is_none());
+        assert_eq!(
+            claims.owner(Path::new(PATH)).as_deref(),
+            Some("a/IMG_0001.jpg")
+        );
+
+        let again = claim(&claims, PATH, "a/IMG_0001.jpg", 10, CollisionPolicy::Error);
+        assert_eq!(again.path, Some(PathBuf::from(PATH)));
+        assert!(again.collision.is_none());
+    }
+
+    #[test]
+    fn test_suffix_policy_ignores_case() {
+        let claims = OutputClaims::new();
+        claim(&claims, PATH, "a/IMG_0001.jpg", 10, CollisionPolicy::Suffix);
+        claim(
+            &claims,
+            "out/2019/IMG_0001 (1).jpg",
+            "b/IMG_0001 (1).jpg",
+            10,
+            CollisionPolicy::Suffix,
+        );
+
+        let renamed = claim(
+            &claims,
+            "out/2019/img_0001.JPG",
+            "c/img_0001.JPG",
+            10,
+            CollisionPolicy::Suffix,
+        );
+        assert_eq!(
+            renamed.path,
+            Some(PathBuf::from("out/2019/img_0001 (2).JPG"))
+        );
+        let collision = renamed.collision.unwrap();
+        assert_eq!(collision.existing, "a/IMG_0001.jpg");
+        assert_eq!(
+            collision.resolution,
+            Resolution::Renamed(PathBuf::from("out/2019/img_0001 (2).JPG"))
+        );
+    }
+
+    #[test]
+    fn test_skip_and_error_policies() {
+        let claims = OutputClaims::new();
+        claim(&claims, PATH, "a/IMG_0001.jpg", 10, CollisionPolicy::Skip);
+
+        let skipped = claim(&claims, PATH, "b/IMG_0001.jpg", 10, CollisionPolicy::Skip);
+        assert_eq!(skipped.path, None);
+        assert_eq!(skipped.collision.unwrap().resolution, Resolution::Skipped);
+
+        let failed = claim(&claims, PATH, "b/IMG_0001.jpg", 10, CollisionPolicy::Error);
+        assert_eq!(failed.path, None);
+        assert_eq!(failed.collision.unwrap().resolution, Resolution::Failed);
+        assert_eq!(
+            claims.owner(Path::new(PATH)).as_deref(),
+            Some("a/IMG_0001.jpg")
+        );
+    }
+
+    #[test]
+    fn test_keep_larger_policy() {
+        let claims = OutputClaims::new();
+        claim(
+            &claims,
+            PATH,
+            "a/IMG_0001.jpg",
+            10,
+            CollisionPolicy::KeepLarger,
+        );
+
+        let smaller = claim(
+            &claims,
+            PATH,
+            "b/IMG_0001.jpg",
+            5,
+            CollisionPolicy::KeepLarger,
+        );
+        assert_eq!(smaller.path, None);
+        assert_eq!(smaller.collision.unwrap().resolution, Resolution::Skipped);
+
+        let larger = claim(
+            &claims,
+            "out/2019/img_0001.jpg",
+            "c/img_0001.jpg",
+            20,
+            CollisionPolicy::KeepLarger,
+        );
+        assert_eq!(larger.path, Some(PathBuf::from(PATH)));
+        assert_eq!(larger.collision.unwrap().resolution, Resolution::Replaced);
+
+        // The replaced file must not be written anymore, while its replacement is
+        assert_eq!(
+            claims.owner(Path::new(PATH)).as_deref(),
+            Some("c/img_0001.jpg")
+        );
+    }
+}
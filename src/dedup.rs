@@ -0,0 +1,147 @@
+use crate::atomic::write_atomically;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// File listing, for each album folder, the media of the album written elsewhere
+pub const ALBUM_MANIFEST_FILE: &str = "album-manifest.json";
+
+/// SHA-256 digest of a file's contents
+pub type ContentHash = [u8; 32];
+
+/// How media that appears in several folders of a takeout is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DedupMode {
+    /// Write every copy
+    #[default]
+    Off,
+    /// Write one copy and hardlink it into the other folders
+    Hardlink,
+    /// Write one copy and symlink it into the other folders with relative links
+    Symlink,
+    /// Write one copy and list the other folders it belongs to in album-manifest.json
+    Manifest,
+}
+
+/// Hashes everything a reader yields
+pub fn hash_contents(reader: &mut dyn Read) -> io::Result<ContentHash> {
+    let mut hasher = Sha256::new();
+    io::copy(reader, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Returns the path of `target` relative to the directory `from_dir`. Both paths must be
+/// relative to the same directory, or both absolute.
+pub fn relative_path(from_dir: &Path, target: &Path) -> PathBuf {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+#[cfg(unix)]
+fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Makes `duplicate` a hardlink or relative symlink to the already written `original`,
+/// creating its parent directories
+pub fn link_file(mode: DedupMode, original: &Path, duplicate: &Path) -> io::Result<()> {
+    let parent = duplicate.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(parent)?;
+    match mode {
+        DedupMode::Hardlink => fs::hard_link(original, duplicate),
+        DedupMode::Symlink => symlink_file(&relative_path(parent, original), duplicate),
+        DedupMode::Off | DedupMode::Manifest => Err(io::Error::other(format!(
+            "{:?} does not link files",
+            mode
+        ))),
+    }
+}
+
+/// Writes the album manifest to the output directory. `albums` maps each album folder to the
+/// media of the album written to other folders, all relative to the output directory.
+pub fn write_album_manifest(
+    output_dir: &Path,
+    albums: &BTreeMap<String, Vec<String>>,
+) -> io::Result<PathBuf> {
+    let path = output_dir.join(ALBUM_MANIFEST_FILE);
+    let json = serde_json::to_string_pretty(albums).map_err(io::Error::other)?;
+    write_atomically(&path, (json + "\n").as_bytes(), None)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_contents() {
+        let hash = hash_contents(&mut b"abc".as_slice()).unwrap();
+        assert_eq!(
+            hash[..4],
+            [0xba, 0x78, 0x16, 0xbf],
+            "SHA-256 of \"abc\" starts with ba7816bf"
+        );
+        assert_ne!(hash, hash_contents(&mut b"abd".as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("out/Trip"), Path::new("out/Photos from 2019/a.jpg")),
+            PathBuf::from("../Photos from 2019/a.jpg")
+        );
+        assert_eq!(
+            relative_path(Path::new("out/Trash/Trip"), Path::new("out/Photos from 2019/a.jpg")),
+            PathBuf::from("../../Photos from 2019/a.jpg")
+        );
+        assert_eq!(
+            relative_path(Path::new("out/Trip"), Path::new("out/Trip/b.jpg")),
+            PathBuf::from("b.jpg")
+        );
+    }
+
+    #[test]
+    fn test_link_file() {
+        let dir = std::env::temp_dir().join(format!("takeout-fixer-dedup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let original = dir.join("Photos from 2019/a.jpg");
+        fs::create_dir_all(original.parent().unwrap()).unwrap();
+        fs::write(&original, b"image").unwrap();
+
+        let hardlink = dir.join("Trip/a.jpg");
+        link_file(DedupMode::Hardlink, &original, &hardlink).unwrap();
+        assert_eq!(fs::read(&hardlink).unwrap(), b"image");
+        assert!(!fs::symlink_metadata(&hardlink).unwrap().is_symlink());
+
+        #[cfg(unix)]
+        {
+            let symlink = dir.join("Party/a.jpg");
+            link_file(DedupMode::Symlink, &original, &symlink).unwrap();
+            assert_eq!(
+                fs::read_link(&symlink).unwrap(),
+                PathBuf::from("../Photos from 2019/a.jpg")
+            );
+            assert_eq!(fs::read(&symlink).unwrap(), b"image");
+        }
+
+        assert!(link_file(DedupMode::Manifest, &original, &dir.join("x.jpg")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
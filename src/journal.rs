@@ -0,0 +1,207 @@
+use crate::dedup::{hash_contents, ContentHash};
+use crate::sync::lock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// File in the output directory recording the media files written so far, one JSON object
+/// per line
+pub const JOURNAL_FILE: &str = ".takeout-fixer-journal.jsonl";
+
+/// A media file that was completely written to the output directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// The path of the media file within its archive
+    pub path: String,
+    /// The archive the media file was read from
+    pub source: PathBuf,
+    /// The size of the media file in the archive, in bytes
+    pub size: u64,
+    /// Where the media file was written, relative to the output directory
+    pub output: PathBuf,
+    /// The SHA-256 hash of the written file, in hex
+    pub hash: String,
+    /// The SHA-256 hash of the sidecar the file was written with, in hex
+    #[serde(default)]
+    pub metadata_hash: Option<String>,
+}
+
+impl JournalEntry {
+    /// Returns true if the file this entry describes is still in the output directory, unchanged
+    pub fn is_intact(&self, output_dir: &Path) -> bool {
+        hash_file(&output_dir.join(&self.output)).is_ok_and(|hash| hash == self.hash)
+    }
+}
+
+/// Formats a hash as lowercase hex
+pub fn hash_hex(hash: &ContentHash) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns the hex SHA-256 hash of a sidecar's contents
+pub fn hash_metadata(json: &str) -> String {
+    hash_data(json.as_bytes())
+}
+
+/// Returns the hex SHA-256 hash of data, e.g. the contents of a file as it is written
+pub fn hash_data(data: &[u8]) -> String {
+    hash_hex(&Sha256::digest(data).into())
+}
+
+/// Returns the hex SHA-256 hash of a file
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    hash_contents(&mut file).map(|hash| hash_hex(&hash))
+}
+
+/// Reads the journal of an output directory, keyed by archive path. A line cut short by an
+/// interrupted run is ignored, as are later entries replaced by a newer one for the same file.
+pub fn read_journal(output_dir: &Path) -> io::Result<HashMap<String, JournalEntry>> {
+    let file = match File::open(output_dir.join(JOURNAL_FILE)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = HashMap::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str::<JournalEntry>(&line?) {
+            entries.insert(entry.path.clone(), entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Number of entries recorded between syncing the journal to disk
+const SYNC_INTERVAL: usize = 64;
+
+/// Appends entries to the journal of an output directory as media files are written
+pub struct Journal {
+    file: Mutex<JournalFile>,
+}
+
+struct JournalFile {
+    file: File,
+    /// Entries written since the journal was last synced to disk
+    unsynced: usize,
+}
+
+impl Journal {
+    /// Opens the journal of an output directory for appending, creating both if needed
+    pub fn open(output_dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(output_dir)?;
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(output_dir.join(JOURNAL_FILE))?;
+        Ok(Self {
+            file: Mutex::new(JournalFile { file, unsynced: 0 }),
+        })
+    }
+
+    /// Records a media file as completely written. The line is handed to the operating system
+    /// before returning, so a crashed run loses at most the entry being written, and it is
+    /// synced to disk every [`SYNC_INTERVAL`] entries, so a power loss loses at most those.
+    pub fn record(&self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+        line.push('\n');
+        let mut journal = lock(&self.file);
+        journal.file.write_all(line.as_bytes())?;
+        journal.unsynced += 1;
+        if journal.unsynced >= SYNC_INTERVAL {
+            journal.file.sync_data()?;
+            journal.unsynced = 0;
+        }
+        Ok(())
+    }
+
+    /// Syncs the entries recorded so far to disk
+    pub fn sync(&self) -> io::Result<()> {
+        let mut journal = lock(&self.file);
+        if journal.unsynced > 0 {
+            journal.file.sync_data()?;
+            journal.unsynced = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Journal {
+    /// Syncs the last entries of a run that stopped without calling [`Journal::sync`]
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, hash: &str) -> JournalEntry {
+        JournalEntry {
+            path: path.to_string(),
+            source: PathBuf::from("takeout-001.zip"),
+            size: 5,
+            output: PathBuf::from("Trip/a.jpg"),
+            hash: hash.to_string(),
+            metadata_hash: None,
+        }
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let dir =
+            std::env::temp_dir().join(format!("takeout-fixer-journal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert!(read_journal(&dir).unwrap().is_empty());
+
+        let journal = Journal::open(&dir).unwrap();
+        journal.record(&entry("Takeout/a.jpg", "00")).unwrap();
+        journal.record(&entry("Takeout/b.jpg", "01")).unwrap();
+        journal.record(&entry("Takeout/a.jpg", "02")).unwrap();
+        drop(journal);
+
+        // An interrupted run can leave half a line behind
+        let mut file = File::options()
+            .append(true)
+            .open(dir.join(JOURNAL_FILE))
+            .unwrap();
+        file.write_all(br#"{"path": "Takeout/c.jpg", "sou"#)
+            .unwrap();
+
+        let entries = read_journal(&dir).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries["Takeout/a.jpg"].hash, "02");
+        assert_eq!(entries["Takeout/a.jpg"].metadata_hash, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hash_metadata() {
+        assert_eq!(
+            hash_metadata("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_is_intact() {
+        let dir = std::env::temp_dir().join(format!("takeout-fixer-intact-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Trip")).unwrap();
+        fs::write(dir.join("Trip/a.jpg"), b"image").unwrap();
+
+        let hash = hash_file(&dir.join("Trip/a.jpg")).unwrap();
+        assert_eq!(hash.len(), 64);
+        assert!(entry("Takeout/a.jpg", &hash).is_intact(&dir));
+
+        fs::write(dir.join("Trip/a.jpg"), b"ima").unwrap();
+        assert!(!entry("Takeout/a.jpg", &hash).is_intact(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!entry("Takeout/a.jpg", &hash).is_intact(&dir));
+    }
+}
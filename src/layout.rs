@@ -0,0 +1,217 @@
+use chrono::{Datelike, NaiveDate};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The layout media is written in unless another is given: one folder per album
+pub const DEFAULT_LAYOUT: &str = "{album}/{filename}";
+
+/// Written in place of the date placeholders for media whose date is unknown
+pub const UNKNOWN_DATE: &str = "Unknown";
+
+/// A piece of a layout template
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Year,
+    Month,
+    Day,
+    Album,
+    Filename,
+}
+
+/// A template for where media is written below the output directory, e.g.
+/// `{year}/{month}/{filename}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    template: String,
+    parts: Vec<Part>,
+}
+
+/// The values the placeholders of a layout are replaced with
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutFields<'a> {
+    /// The album folder of the media, which may be empty or contain `/`
+    pub album: &'a str,
+    /// The file name of the media
+    pub filename: &'a str,
+    /// The local date the media was taken, if known
+    pub date: Option<NaiveDate>,
+}
+
+impl Layout {
+    /// Returns true if the layout needs the date media was taken
+    pub fn uses_date(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Year | Part::Month | Part::Day))
+    }
+
+    /// Returns the path of a media file relative to the output directory. Empty folder names,
+    /// e.g. from media outside any album, are left out.
+    pub fn render(&self, fields: &LayoutFields) -> PathBuf {
+        let date_part = |format: fn(NaiveDate) -> String| {
+            fields.date.map_or_else(|| UNKNOWN_DATE.to_string(), format)
+        };
+
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::Year => rendered.push_str(&date_part(|date| format!("{:04}", date.year()))),
+                Part::Month => rendered.push_str(&date_part(|date| format!("{:02}", date.month()))),
+                Part::Day => rendered.push_str(&date_part(|date| format!("{:02}", date.day()))),
+                Part::Album => rendered.push_str(fields.album),
+                Part::Filename => rendered.push_str(fields.filename),
+            }
+        }
+
+        rendered
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        DEFAULT_LAYOUT.parse().expect("the default layout is valid")
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    /// Parses a template of folder names separated by `/`, using the placeholders `{year}`,
+    /// `{month}`, `{day}`, `{album}` and `{filename}`. `{filename}` must end the template.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while !rest.is_empty() {
+            let Some(start) = rest.find('{') else {
+                parts.push(Part::Literal(rest.to_string()));
+                break;
+            };
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed placeholder in layout '{}'", s))?;
+            let part = match &rest[start + 1..start + end] {
+                "year" => Part::Year,
+                "month" => Part::Month,
+                "day" => Part::Day,
+                "album" => Part::Album,
+                "filename" => Part::Filename,
+                name => {
+                    return Err(format!(
+                        "Unknown placeholder '{{{}}}' in layout '{}', expected one of {{year}}, {{month}}, {{day}}, {{album}} or {{filename}}",
+                        name, s
+                    ));
+                }
+            };
+            parts.push(part);
+            rest = &rest[start + end + 1..];
+        }
+
+        if parts.last() != Some(&Part::Filename) {
+            return Err(format!("Layout '{}' must end with {{filename}}", s));
+        }
+        let escapes = s.starts_with('/')
+            || s.contains('\\')
+            || s.split('/')
+                .any(|segment| segment == "." || segment == "..");
+        if escapes {
+            return Err(format!(
+                "Layout '{}' must stay inside the output directory and separate folders with /",
+                s
+            ));
+        }
+
+        Ok(Self {
+            template: s.to_string(),
+            parts,
+        })
+    }
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(layout: &str, album: &str, date: Option<NaiveDate>) -> PathBuf {
+        let layout: Layout = layout.parse().unwrap();
+        layout.render(&LayoutFields {
+            album,
+            filename: "IMG_0001.jpg",
+            date,
+        })
+    }
+
+    #[test]
+    fn test_default_layout() {
+        let layout = Layout::default();
+        assert!(!layout.uses_date());
+        assert_eq!(layout.to_string(), DEFAULT_LAYOUT);
+        assert_eq!(
+            render(DEFAULT_LAYOUT, "Trip/Day 1", None),
+            PathBuf::from("Trip/Day 1/IMG_0001.jpg")
+        );
+        assert_eq!(
+            render(DEFAULT_LAYOUT, "", None),
+            PathBuf::from("IMG_0001.jpg")
+        );
+    }
+
+    #[test]
+    fn test_date_layouts() {
+        let date = NaiveDate::from_ymd_opt(2019, 7, 3);
+        assert!(
+            "{year}/{month}/{filename}"
+                .parse::<Layout>()
+                .unwrap()
+                .uses_date()
+        );
+        assert_eq!(
+            render("{year}/{month}/{filename}", "Trip", date),
+            PathBuf::from("2019/07/IMG_0001.jpg")
+        );
+        assert_eq!(
+            render("{year}/{album}/{filename}", "Trip", date),
+            PathBuf::from("2019/Trip/IMG_0001.jpg")
+        );
+        assert_eq!(
+            render("{year}-{month}-{day}/{filename}", "Trip", date),
+            PathBuf::from("2019-07-03/IMG_0001.jpg")
+        );
+        assert_eq!(
+            render("{year}/{month}/{filename}", "Trip", None),
+            PathBuf::from("Unknown/Unknown/IMG_0001.jpg")
+        );
+    }
+
+    #[test]
+    fn test_invalid_layouts() {
+        for layout in [
+            "{year}/{month}",
+            "{year}/{filename}/x",
+            "{year/{filename}",
+            "{week}/{filename}",
+            "/{filename}",
+            "../{filename}",
+            "{album}\\{filename}",
+        ] {
+            assert!(
+                layout.parse::<Layout>().is_err(),
+                "{} should be rejected",
+                layout
+            );
+        }
+    }
+}
@@ -1,16 +1,25 @@
 pub mod archive;
+pub mod atomic;
 pub mod cli;
+pub mod collision;
+pub mod dedup;
+pub mod journal;
+pub mod layout;
 pub mod metadata;
 pub mod process;
+pub mod source;
+pub mod sync;
+pub mod timezone;
+pub mod video;
+pub mod xmp;
 
 use archive::{ArchiveFile, Takeout, TakeoutError};
-use flate2::read::GzDecoder;
-use process::process_takeout;
-use std::fs::File;
-use std::io::BufReader;
+use dedup::{DedupMode, ALBUM_MANIFEST_FILE};
+use journal::JOURNAL_FILE;
+use metadata::{FieldPreferences, MetadataOptions};
+use process::{process_takeout, ArchivedMode, ProcessOptions, TrashedMode};
+use source::{open_source, ArchiveSource};
 use std::path::Path;
-use tar::Archive as TarArchive;
-use zip::ZipArchive;
 
 pub fn run(args: cli::Cli) {
     if args.debug {
@@ -20,16 +29,43 @@ pub fn run(args: cli::Cli) {
         println!("Dry run mode - no changes will be made");
     }
 
+    let update = matches!(args.command, Some(cli::Commands::Update { .. }));
     match args.command {
-        Some(cli::Commands::Fix { paths }) => {
+        Some(cli::Commands::Fix { paths }) | Some(cli::Commands::Update { paths }) => {
+            // Updates merge into the output of an earlier run, which its journal describes
+            if update && !args.output.join(JOURNAL_FILE).exists() {
+                eprintln!(
+                    "Error: Output directory '{}' has no {} from an earlier run to update.",
+                    args.output.display(),
+                    JOURNAL_FILE
+                );
+                std::process::exit(1);
+            }
+
             // Check if output directory already exists
-            if args.output.exists() && !args.dry_run {
+            if args.output.exists() && !args.dry_run && !args.resume && !update {
                 eprintln!(
-                    "Error: Output directory '{}' already exists. Please remove it or specify a different output directory with --output.",
+                    "Error: Output directory '{}' already exists. Please remove it, specify a different output directory with --output, or pass --resume to continue an interrupted run.",
                     args.output.display()
                 );
                 std::process::exit(1);
             }
+            if args.resume && args.output.exists() && !args.output.join(JOURNAL_FILE).exists() {
+                eprintln!(
+                    "Error: Output directory '{}' has no {} to resume from.",
+                    args.output.display(),
+                    JOURNAL_FILE
+                );
+                std::process::exit(1);
+            }
+
+            // A deduplicated file is shared by all its albums, so it cannot carry each one's title
+            if args.album_keywords && args.dedup != DedupMode::Off {
+                eprintln!(
+                    "Error: --album-keywords cannot be combined with --dedup, as media in several albums is only written once."
+                );
+                std::process::exit(1);
+            }
 
             println!("Output directory: {}", args.output.display());
 
@@ -49,6 +85,7 @@ pub fn run(args: cli::Cli) {
 
             // Build the Takeout structure from all archives
             let mut takeout = Takeout::new();
+            takeout.set_edited_suffixes(args.edited_suffixes.clone());
             for file in &expanded_files {
                 println!("\nReading archive: {}", file.display());
                 if let Err(e) = load_archive_into_takeout(&mut takeout, file, &photo_path_prefix, args.debug) {
@@ -59,22 +96,45 @@ pub fn run(args: cli::Cli) {
 
             println!("\n=== Takeout Summary ===");
             println!("Total files: {}", takeout.len());
-            println!("Source archives: {}", takeout.source_archives().len());
+            println!("Source archives: {}", takeout.sources().count());
 
             if args.debug {
                 println!("\n{:#?}", takeout);
             }
 
             // Process the takeout (fix metadata and output files)
-            let show_progress = !args.no_progress;
-            match process_takeout(
-                &takeout,
-                &args.output,
-                &photo_path_prefix,
-                args.dry_run,
-                args.debug,
-                show_progress,
-            ) {
+            let options = ProcessOptions {
+                dry_run: args.dry_run,
+                debug: args.debug,
+                show_progress: !args.no_progress,
+                set_file_times: !args.no_file_times,
+                metadata_mode: args.metadata_mode,
+                metadata: MetadataOptions {
+                    timezone: args.timezone,
+                    prefer: FieldPreferences {
+                        date: args.prefer_date.unwrap_or(args.prefer),
+                        gps: args.prefer_gps.unwrap_or(args.prefer),
+                        description: args.prefer_description.unwrap_or(args.prefer),
+                    },
+                    favorite_rating: args.favorite_rating,
+                    favorite_keyword: args.favorite_keyword.clone(),
+                },
+                strict: args.strict,
+                trashed: args.trashed,
+                archived: args.archived,
+                album_keywords: args.album_keywords,
+                dedup: args.dedup,
+                layout: args.layout.clone(),
+                on_collision: args.on_collision,
+                resume: args.resume,
+                update,
+                jobs: args.jobs.unwrap_or_else(|| {
+                    std::thread::available_parallelism()
+                        .map(|n| n.get())
+                        .unwrap_or(1)
+                }),
+            };
+            match process_takeout(&takeout, &args.output, &photo_path_prefix, &options) {
                 Ok(stats) => {
                     println!("\n=== Processing Complete ===");
                     println!("Total media processed: {}", stats.images_processed);
@@ -83,6 +143,70 @@ pub fn run(args: cli::Cli) {
                     println!("Videos copied: {}", stats.videos_copied);
                     println!("Metadata applied: {}", stats.metadata_applied);
                     println!("Copied without metadata: {}", stats.media_copied_without_metadata);
+                    if stats.edited_matched > 0 {
+                        println!("Edited media matched to original metadata: {}", stats.edited_matched);
+                    }
+                    if stats.fields_kept > 0 || stats.fields_overwritten > 0 {
+                        println!("Existing fields kept: {}", stats.fields_kept);
+                        println!("Existing fields overwritten: {}", stats.fields_overwritten);
+                    }
+                    if stats.companions_matched > 0 {
+                        println!("Live/Motion Photo videos paired with their still: {}", stats.companions_matched);
+                    }
+                    if stats.title_matched > 0 {
+                        println!("Media matched by sidecar title: {}", stats.title_matched);
+                    }
+                    if stats.title_ambiguous > 0 {
+                        println!("Ambiguous title matches (left without metadata): {}", stats.title_ambiguous);
+                    }
+                    if stats.trashed > 0 {
+                        let action = match args.trashed {
+                            TrashedMode::Skip => "skipped",
+                            TrashedMode::Include => "included",
+                            TrashedMode::Separate => "written to Trash",
+                        };
+                        println!("Trashed media ({}): {}", action, stats.trashed);
+                    }
+                    if stats.archived > 0 {
+                        let action = match args.archived {
+                            ArchivedMode::Include => "included",
+                            ArchivedMode::Separate => "written to Archive",
+                        };
+                        println!("Archived media ({}): {}", action, stats.archived);
+                    }
+                    if update {
+                        println!("Added: {}", stats.added);
+                        println!("Changed: {}", stats.changed);
+                        println!("Unchanged: {}", stats.unchanged);
+                    }
+                    if stats.resumed > 0 {
+                        println!("Already written by an earlier run: {}", stats.resumed);
+                    }
+                    if stats.duplicates > 0 {
+                        let action = match args.dedup {
+                            DedupMode::Off => "written".to_string(),
+                            DedupMode::Hardlink => "hardlinked".to_string(),
+                            DedupMode::Symlink => "symlinked".to_string(),
+                            DedupMode::Manifest => format!("listed in {}", ALBUM_MANIFEST_FILE),
+                        };
+                        println!("Duplicate media ({}): {}", action, stats.duplicates);
+                    }
+                    if !stats.collisions.is_empty() {
+                        println!("Output path collisions: {}", stats.collisions.len());
+                        for collision in &stats.collisions {
+                            println!("  {}", collision);
+                        }
+                    }
+                    if !stats.unknown_fields.is_empty() {
+                        if args.strict {
+                            println!("Unknown sidecar fields:");
+                        } else {
+                            println!("Unknown sidecar fields (ignored):");
+                        }
+                        for (field, count) in &stats.unknown_fields {
+                            println!("  {}: {}", field, count);
+                        }
+                    }
                     if stats.unused_metadata_files > 0 {
                         println!("Unused metadata files: {}", stats.unused_metadata_files);
                     }
@@ -111,51 +235,29 @@ fn load_archive_into_takeout(
     photo_path_prefix: &str,
     debug: bool,
 ) -> Result<(), TakeoutError> {
-    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-
-    takeout.add_source_archive(path.to_path_buf());
-
-    if file_name.ends_with(".zip") {
-        load_zip_into_takeout(takeout, path, photo_path_prefix, debug)
-    } else if file_name.ends_with(".tar.gz") {
-        load_tar_gz_into_takeout(takeout, path, photo_path_prefix, debug)
-    } else {
-        Err(TakeoutError::Other(format!(
-            "Unsupported archive format: {}",
-            file_name
-        )))
-    }
+    let source = open_source(path)?;
+    load_source_into_takeout(takeout, source, photo_path_prefix, debug)
 }
 
-fn load_zip_into_takeout(
+/// Indexes all files below `photo_path_prefix` in a source and adds the source to the takeout.
+/// This is the entry point for library users supplying their own [`ArchiveSource`].
+pub fn load_source_into_takeout(
     takeout: &mut Takeout,
-    path: &Path,
+    source: Box<dyn ArchiveSource>,
     photo_path_prefix: &str,
     debug: bool,
 ) -> Result<(), TakeoutError> {
-    let file =
-        File::open(path).map_err(|e| TakeoutError::Other(format!("Failed to open file: {}", e)))?;
-    let reader = BufReader::new(file);
-    let mut archive = ZipArchive::new(reader)
-        .map_err(|e| TakeoutError::Other(format!("Failed to read zip archive: {}", e)))?;
+    let source_path = source.path().to_path_buf();
 
     let mut count = 0;
-    for i in 0..archive.len() {
-        let entry = archive
-            .by_index(i)
-            .map_err(|e| TakeoutError::Other(format!("Failed to read entry: {}", e)))?;
-        let entry_path = entry.name().to_string();
-
-        if entry_path.starts_with(photo_path_prefix) && !entry.is_dir() {
-            let archive_file = ArchiveFile::new(
-                entry_path.clone(),
-                path.to_path_buf(),
-                i,
-                entry.size(),
-            );
+    for entry in source.entries()? {
+        if entry.path.starts_with(photo_path_prefix) {
+            let archive_file =
+                ArchiveFile::new(entry.path, source_path.clone(), entry.index, entry.size)
+                    .with_modified(entry.modified);
 
             if debug {
-                println!("  Found: {}", entry_path);
+                println!("  Found: {}", archive_file.archive_path);
             }
 
             takeout.insert(archive_file)?;
@@ -163,57 +265,8 @@ fn load_zip_into_takeout(
         }
     }
 
-    println!("  Loaded {} files from {}", count, path.display());
-    Ok(())
-}
-
-fn load_tar_gz_into_takeout(
-    takeout: &mut Takeout,
-    path: &Path,
-    photo_path_prefix: &str,
-    debug: bool,
-) -> Result<(), TakeoutError> {
-    let file =
-        File::open(path).map_err(|e| TakeoutError::Other(format!("Failed to open file: {}", e)))?;
-    let reader = BufReader::new(file);
-    let decoder = GzDecoder::new(reader);
-    let mut archive = TarArchive::new(decoder);
-
-    let entries = archive
-        .entries()
-        .map_err(|e| TakeoutError::Other(format!("Failed to read tar entries: {}", e)))?;
-
-    let mut count = 0;
-    let mut index = 0;
-    for entry in entries {
-        let entry =
-            entry.map_err(|e| TakeoutError::Other(format!("Failed to read entry: {}", e)))?;
-        let entry_path = entry
-            .path()
-            .map_err(|e| TakeoutError::Other(format!("Failed to get path: {}", e)))?;
-        let mut entry_path_str = entry_path.to_string_lossy().to_string();
-        if let Some(stripped) = entry_path_str.strip_prefix("./") {
-            entry_path_str = stripped.to_string();
-        }
-
-        if entry_path_str.starts_with(photo_path_prefix) && entry.header().entry_type().is_file() {
-            let archive_file = ArchiveFile::new(
-                entry_path_str.clone(),
-                path.to_path_buf(),
-                index,
-                entry.size(),
-            );
-
-            if debug {
-                println!("  Found: {}", entry_path_str);
-            }
-
-            takeout.insert(archive_file)?;
-            count += 1;
-        }
-        index += 1;
-    }
+    takeout.add_source(source);
 
-    println!("  Loaded {} files from {}", count, path.display());
+    println!("  Loaded {} files from {}", count, source_path.display());
     Ok(())
 }
@@ -1,21 +1,50 @@
 use little_exif::metadata::Metadata;
 use little_exif::exif_tag::ExifTag;
 use little_exif::rational::uR64;
+use crate::timezone::{format_exif_offset, timezone_for_location, Timezone};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDateTime};
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Descriptions cameras write by default, which say nothing about the photo
+const PLACEHOLDER_DESCRIPTIONS: &[&str] = &[
+    "OLYMPUS DIGITAL CAMERA",
+    "SONY DSC",
+    "DIGITAL CAMERA",
+    "KODAK Digital Still Camera",
+    "Samsung Digital Camera",
+    "MINOLTA DIGITAL CAMERA",
+];
+
+/// Rating given to media marked as a favorite in Google Photos, unless configured otherwise
+pub const DEFAULT_FAVORITE_RATING: u8 = 5;
+
+/// Earliest accepted timestamp (1800-01-01 00:00:00 UTC), before the first photographs. Earlier
+/// dates in sidecars are errors, and earlier dates in EXIF are treated as invalid.
+const MIN_TIMESTAMP: i64 = -5_364_662_400;
+
+/// Latest accepted timestamp (9999-12-31 23:59:59 UTC), as EXIF dates have four-digit years
+const MAX_TIMESTAMP: i64 = 253_402_300_799;
+
+/// Existing GPS coordinates within this many degrees of the sidecar's (about 50 m) agree with it
+const GPS_AGREEMENT_DEGREES: f64 = 0.0005;
 
 /// Represents a timestamp in Google's supplemental metadata format
 #[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
 pub struct GoogleTimestamp {
     /// Unix timestamp as a string
     pub timestamp: String,
     /// Human-readable formatted date
     pub formatted: String,
+    /// Fields this tool does not know about
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
 }
 
 /// Represents geo data in Google's supplemental metadata format
 #[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
 pub struct GeoData {
     pub latitude: f64,
     pub longitude: f64,
@@ -24,6 +53,19 @@ pub struct GeoData {
     pub latitude_span: f64,
     #[serde(default)]
     pub longitude_span: f64,
+    /// Fields this tool does not know about
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+/// A person tagged in a photo in Google Photos
+#[derive(Debug, Deserialize)]
+pub struct Person {
+    #[serde(default)]
+    pub name: String,
+    /// Fields this tool does not know about
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
 }
 
 /// Represents the origin information for how a photo was added to Google Photos
@@ -36,11 +78,14 @@ pub struct GooglePhotosOrigin {
     pub mobile_upload: Option<serde_json::Value>,
     #[serde(default)]
     pub from_partner_sharing: Option<serde_json::Value>,
+    /// Fields this tool does not know about
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
 }
 
 /// Represents the Google Photos supplemental metadata JSON format
 #[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[serde(rename_all = "camelCase")]
 pub struct GoogleSupplementalMetadata {
     pub title: String,
     #[serde(default)]
@@ -49,6 +94,8 @@ pub struct GoogleSupplementalMetadata {
     pub image_views: Option<String>,
     pub creation_time: Option<GoogleTimestamp>,
     pub photo_taken_time: Option<GoogleTimestamp>,
+    #[serde(default)]
+    pub photo_last_modified_time: Option<GoogleTimestamp>,
     pub geo_data: Option<GeoData>,
     #[serde(default)]
     pub url: Option<String>,
@@ -57,7 +104,7 @@ pub struct GoogleSupplementalMetadata {
     #[serde(default)]
     pub geo_data_exif: Option<GeoData>,
     #[serde(default)]
-    pub people: Option<serde_json::Value>,
+    pub people: Vec<Person>,
     #[serde(default)]
     pub enrichments: Option<serde_json::Value>,
     #[serde(default)]
@@ -68,10 +115,127 @@ pub struct GoogleSupplementalMetadata {
     pub trashed: Option<bool>,
     #[serde(default)]
     pub app_source: Option<serde_json::Value>,
+    /// Fields this tool does not know about
+    #[serde(flatten)]
+    pub unknown_fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl GoogleSupplementalMetadata {
+    /// Returns when the photo was taken as a Unix timestamp, if present
+    pub fn taken_timestamp(&self) -> Result<Option<i64>, MetadataError> {
+        self.photo_taken_time
+            .as_ref()
+            .map_or(Ok(None), |t| parse_timestamp(&t.timestamp))
+    }
+
+    /// Returns the location the photo was taken at, unless it is missing or zeroed out
+    pub fn location(&self) -> Option<&GeoData> {
+        self.geo_data
+            .as_ref()
+            .filter(|geo| geo.latitude != 0.0 || geo.longitude != 0.0)
+    }
+
+    /// Returns the names of the people tagged in the photo
+    pub fn people_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for person in &self.people {
+            let name = person.name.trim();
+            if !name.is_empty() && !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+        names
+    }
+
+    /// Returns the offset from UTC in seconds of the local time at `timestamp` where the photo
+    /// was taken. This is the timezone of its location, else `fallback`, else UTC.
+    pub fn utc_offset_at(&self, timestamp: i64, fallback: Option<Timezone>) -> i32 {
+        self.location()
+            .map(|geo| timezone_for_location(geo.latitude, geo.longitude))
+            .or(fallback)
+            .map_or(0, |tz| tz.offset_at(timestamp))
+    }
+
+    /// Returns the fields this tool does not know about, with nested fields given by their
+    /// path, e.g. `geoData.accuracy`
+    pub fn unknown_fields(&self) -> Vec<String> {
+        let mut fields: Vec<String> = self.unknown_fields.keys().cloned().collect();
+        let nested = [
+            ("creationTime", self.creation_time.as_ref().map(|t| &t.unknown_fields)),
+            ("photoTakenTime", self.photo_taken_time.as_ref().map(|t| &t.unknown_fields)),
+            ("geoData", self.geo_data.as_ref().map(|g| &g.unknown_fields)),
+            ("geoDataExif", self.geo_data_exif.as_ref().map(|g| &g.unknown_fields)),
+            (
+                "googlePhotosOrigin",
+                self.google_photos_origin.as_ref().map(|o| &o.unknown_fields),
+            ),
+        ];
+        for (parent, unknown) in nested {
+            for field in unknown.into_iter().flat_map(|u| u.keys()) {
+                fields.push(format!("{}.{}", parent, field));
+            }
+        }
+        for field in self.people.iter().flat_map(|person| person.unknown_fields.keys()) {
+            fields.push(format!("people.{}", field));
+        }
+        fields.sort();
+        fields.dedup();
+        fields
+    }
+
+    /// Fails with [`MetadataError::UnknownField`] if the sidecar has any fields this tool does
+    /// not know about. `json` is the sidecar's contents, included in the error.
+    pub fn reject_unknown_fields(&self, json: &str) -> Result<(), MetadataError> {
+        match self.unknown_fields().into_iter().next() {
+            Some(field) => Err(MetadataError::UnknownField {
+                field,
+                json: json.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns when the photo was taken, or else when it was uploaded, as a Unix timestamp
+    pub fn taken_or_created_timestamp(&self) -> Result<Option<i64>, MetadataError> {
+        match self.taken_timestamp()? {
+            Some(timestamp) => Ok(Some(timestamp)),
+            None => self
+                .creation_time
+                .as_ref()
+                .map_or(Ok(None), |t| parse_timestamp(&t.timestamp)),
+        }
+    }
+
+    /// Returns when the metadata was last edited in Google Photos as a Unix timestamp, falling
+    /// back to when the media was uploaded. Unusable timestamps count as unknown.
+    pub fn modified_timestamp(&self) -> Option<i64> {
+        [&self.photo_last_modified_time, &self.creation_time]
+            .into_iter()
+            .flatten()
+            .find_map(|t| parse_timestamp(&t.timestamp).ok().flatten())
+    }
+}
+
+/// Parses a timestamp string from Google metadata. Empty strings mean the time is unknown.
+fn parse_timestamp(value: &str) -> Result<Option<i64>, MetadataError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let timestamp = value
+        .parse::<i64>()
+        .map_err(|_| MetadataError::InvalidTimestamp(format!("'{}' is not a number", value)))?;
+    if !(MIN_TIMESTAMP..=MAX_TIMESTAMP).contains(&timestamp) {
+        return Err(MetadataError::InvalidTimestamp(format!(
+            "{} is not between the years 1800 and 9999",
+            timestamp
+        )));
+    }
+    Ok(Some(timestamp))
 }
 
 /// Error type for metadata operations
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MetadataError {
     /// JSON parsing failed, includes the raw JSON for debugging
     JsonParseError {
@@ -83,6 +247,7 @@ pub enum MetadataError {
         field: String,
         json: String,
     },
+    /// A timestamp that is not a number or lies outside the supported range
     InvalidTimestamp(String),
 }
 
@@ -130,57 +295,362 @@ impl std::fmt::Display for MetadataError {
 
 impl std::error::Error for MetadataError {}
 
-/// Parses Google supplemental metadata JSON and updates an existing Metadata object.
+/// Represents the metadata.json describing an album in Takeout
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumMetadata {
+    /// The album title as shown in Google Photos, before Takeout sanitized it into a folder name
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub date: Option<GoogleTimestamp>,
+    /// Fields this tool does not use
+    #[serde(flatten)]
+    pub other_fields: BTreeMap<String, serde_json::Value>,
+}
+
+/// Parses the metadata.json of an album
+pub fn parse_album_metadata(json: &str) -> Result<AlbumMetadata, MetadataError> {
+    serde_json::from_str(json).map_err(|e| MetadataError::JsonParseError {
+        message: e.to_string(),
+        json: json.to_string(),
+    })
+}
+
+/// Only the title of a sidecar, which is the original file name of the media it describes
+#[derive(Deserialize)]
+struct SidecarTitle {
+    title: String,
+}
+
+/// Extracts the `title` field from Google supplemental metadata JSON, if present.
+/// Unlike full parsing this never fails on unknown fields.
+pub fn parse_title(json: &str) -> Option<String> {
+    serde_json::from_str::<SidecarTitle>(json).ok().map(|t| t.title)
+}
+
+/// Parses Google supplemental metadata JSON. Fields this tool does not know about are
+/// collected rather than rejected, see [`GoogleSupplementalMetadata::unknown_fields`].
+pub fn parse_google_metadata(json: &str) -> Result<GoogleSupplementalMetadata, MetadataError> {
+    serde_json::from_str(json).map_err(|e| MetadataError::JsonParseError {
+        message: e.to_string(),
+        json: json.to_string(),
+    })
+}
+
+/// Which value wins when a media file already has a value the sidecar also provides
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Preference {
+    /// Always write the sidecar value
+    #[default]
+    Sidecar,
+    /// Keep existing values, only filling in missing or clearly wrong ones
+    Existing,
+    /// Keep whichever value was changed last: the sidecar's if it was edited in Google Photos
+    /// after the media file's `ModifyDate`, or the file has none
+    Newest,
+}
+
+/// The [`Preference`] for each field that media files may already have
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FieldPreferences {
+    pub date: Preference,
+    pub gps: Preference,
+    pub description: Preference,
+}
+
+impl FieldPreferences {
+    /// Uses the same preference for every field
+    pub fn all(preference: Preference) -> Self {
+        Self {
+            date: preference,
+            gps: preference,
+            description: preference,
+        }
+    }
+}
+
+/// Counts of existing fields that were kept or overwritten with sidecar values.
+/// Fields the media did not have yet are not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FieldCounts {
+    pub kept: usize,
+    pub overwritten: usize,
+}
+
+/// How an existing field compares to the sidecar's value for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExistingValue {
+    Missing,
+    /// Present but unusable, e.g. a zeroed date or a camera's placeholder description
+    Invalid,
+    Agrees,
+    Differs,
+}
+
+impl FieldCounts {
+    /// Decides whether the sidecar value should be written over an existing value,
+    /// counting the outcome. Values that agree with the sidecar count as kept either way.
+    fn should_write(
+        &mut self,
+        preference: Preference,
+        existing: ExistingValue,
+        sidecar_is_newer: bool,
+    ) -> bool {
+        let write = match (existing, preference) {
+            (ExistingValue::Missing, _) => return true,
+            (ExistingValue::Invalid, _) | (_, Preference::Sidecar) => true,
+            (_, Preference::Existing) | (ExistingValue::Agrees, Preference::Newest) => false,
+            (ExistingValue::Differs, Preference::Newest) => sidecar_is_newer,
+        };
+        if write && existing != ExistingValue::Agrees {
+            self.overwritten += 1;
+        } else {
+            self.kept += 1;
+        }
+        write
+    }
+}
+
+/// Options controlling how Google metadata is written into media files
+#[derive(Debug, Clone)]
+pub struct MetadataOptions {
+    /// Timezone for media without a location. Defaults to UTC.
+    pub timezone: Option<Timezone>,
+    /// Whether existing values or sidecar values win
+    pub prefer: FieldPreferences,
+    /// Rating, from 1 to 5 stars, given to media marked as a favorite in Google Photos
+    pub favorite_rating: u8,
+    /// Keyword added to media marked as a favorite in Google Photos
+    pub favorite_keyword: Option<String>,
+}
+
+impl Default for MetadataOptions {
+    fn default() -> Self {
+        Self {
+            timezone: None,
+            prefer: FieldPreferences::default(),
+            favorite_rating: DEFAULT_FAVORITE_RATING,
+            favorite_keyword: None,
+        }
+    }
+}
+
+/// Reads a string tag, without the NUL padding some cameras add
+fn existing_string(metadata: &Metadata, tag: ExifTag) -> Option<String> {
+    let value = match metadata.get_tag(&tag).next()? {
+        ExifTag::DateTimeOriginal(s)
+        | ExifTag::OffsetTimeOriginal(s)
+        | ExifTag::ModifyDate(s)
+        | ExifTag::OffsetTime(s)
+        | ExifTag::ImageDescription(s) => s,
+        ExifTag::GPSLatitudeRef(s) | ExifTag::GPSLongitudeRef(s) => s,
+        _ => return None,
+    };
+    Some(value.trim_end_matches('\0').trim().to_string())
+}
+
+/// Returns the local capture time in an image's `DateTimeOriginal`, ignoring the placeholder
+/// dates of cameras with an unset clock
+pub fn exif_capture_time(metadata: &Metadata) -> Option<NaiveDateTime> {
+    let datetime = existing_string(metadata, ExifTag::DateTimeOriginal(String::new()))?;
+    NaiveDateTime::parse_from_str(&datetime, "%Y:%m:%d %H:%M:%S")
+        .ok()
+        .filter(|local| local.and_utc().timestamp() >= MIN_TIMESTAMP)
+}
+
+/// Converts an EXIF local time to a Unix timestamp, using the offset in `offset_tag` if the
+/// media has one and `fallback_offset` otherwise
+fn exif_timestamp(
+    metadata: &Metadata,
+    local: NaiveDateTime,
+    offset_tag: ExifTag,
+    fallback_offset: i32,
+) -> Option<i64> {
+    let offset = existing_string(metadata, offset_tag)
+        .and_then(|offset| {
+            DateTime::parse_from_str(&format!("2000-01-01 00:00:00 {}", offset), "%Y-%m-%d %H:%M:%S %:z")
+                .ok()
+        })
+        .map(|date| date.offset().local_minus_utc())
+        .unwrap_or(fallback_offset);
+    FixedOffset::east_opt(offset)
+        .and_then(|offset| local.and_local_timezone(offset).single())
+        .map(|date| date.timestamp())
+}
+
+/// Returns when the metadata of a media file was last changed according to its `ModifyDate`,
+/// as a Unix timestamp. Without an `OffsetTime`, it is assumed to be in `fallback_offset`.
+fn exif_modified_timestamp(metadata: &Metadata, fallback_offset: i32) -> Option<i64> {
+    let datetime = existing_string(metadata, ExifTag::ModifyDate(String::new()))?;
+    let local = NaiveDateTime::parse_from_str(&datetime, "%Y:%m:%d %H:%M:%S")
+        .ok()
+        .filter(|local| local.and_utc().timestamp() >= MIN_TIMESTAMP)?;
+    exif_timestamp(metadata, local, ExifTag::OffsetTime(String::new()), fallback_offset)
+}
+
+/// Compares an existing `DateTimeOriginal` with the sidecar's capture time. Without an
+/// `OffsetTimeOriginal`, the existing local time is assumed to be in `fallback_offset`.
+fn existing_date(metadata: &Metadata, timestamp: i64, fallback_offset: i32) -> ExistingValue {
+    let Some(datetime) = existing_string(metadata, ExifTag::DateTimeOriginal(String::new())) else {
+        return ExistingValue::Missing;
+    };
+    let Ok(local) = NaiveDateTime::parse_from_str(&datetime, "%Y:%m:%d %H:%M:%S") else {
+        return ExistingValue::Invalid;
+    };
+    let offset_tag = ExifTag::OffsetTimeOriginal(String::new());
+    let Some(existing) = exif_timestamp(metadata, local, offset_tag, fallback_offset) else {
+        return ExistingValue::Invalid;
+    };
+
+    // Cameras with an unset clock write dates like 0000:00:00 or far in the future
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(i64::MAX, |d| d.as_secs() as i64);
+    if existing < MIN_TIMESTAMP || existing > now + 86400 {
+        return ExistingValue::Invalid;
+    }
+
+    if existing == timestamp {
+        ExistingValue::Agrees
+    } else {
+        ExistingValue::Differs
+    }
+}
+
+/// Converts an EXIF GPS coordinate and its reference ("N", "S", "E" or "W") to decimal degrees
+fn exif_dms_to_decimal(values: &[uR64], reference: &str) -> Option<f64> {
+    let [degrees, minutes, seconds] = values else {
+        return None;
+    };
+    let mut decimal = 0.0;
+    for (value, scale) in [(degrees, 1.0), (minutes, 60.0), (seconds, 3600.0)] {
+        if value.denominator == 0 {
+            return None;
+        }
+        decimal += value.nominator as f64 / value.denominator as f64 / scale;
+    }
+    match reference {
+        "S" | "W" => Some(-decimal),
+        _ => Some(decimal),
+    }
+}
+
+/// Compares existing GPS coordinates with the sidecar's location
+fn existing_gps(metadata: &Metadata, geo: &GeoData) -> ExistingValue {
+    let coordinate = |tag: ExifTag, reference: ExifTag| {
+        let values = match metadata.get_tag(&tag).next()? {
+            ExifTag::GPSLatitude(values) | ExifTag::GPSLongitude(values) => values.clone(),
+            _ => return None,
+        };
+        let reference = existing_string(metadata, reference).unwrap_or_default();
+        Some(exif_dms_to_decimal(&values, &reference))
+    };
+    let latitude = coordinate(
+        ExifTag::GPSLatitude(Vec::new()),
+        ExifTag::GPSLatitudeRef(String::new()),
+    );
+    let longitude = coordinate(
+        ExifTag::GPSLongitude(Vec::new()),
+        ExifTag::GPSLongitudeRef(String::new()),
+    );
+
+    match (latitude, longitude) {
+        (None, None) => ExistingValue::Missing,
+        (Some(Some(latitude)), Some(Some(longitude)))
+            if latitude.abs() <= 90.0
+                && longitude.abs() <= 180.0
+                && (latitude != 0.0 || longitude != 0.0) =>
+        {
+            if (latitude - geo.latitude).abs() <= GPS_AGREEMENT_DEGREES
+                && (longitude - geo.longitude).abs() <= GPS_AGREEMENT_DEGREES
+            {
+                ExistingValue::Agrees
+            } else {
+                ExistingValue::Differs
+            }
+        }
+        _ => ExistingValue::Invalid,
+    }
+}
+
+/// Compares an existing `ImageDescription` with the sidecar's description
+fn existing_description(metadata: &Metadata, description: &str) -> ExistingValue {
+    match existing_string(metadata, ExifTag::ImageDescription(String::new())) {
+        None => ExistingValue::Missing,
+        Some(existing) if existing.is_empty() => ExistingValue::Missing,
+        Some(existing)
+            if PLACEHOLDER_DESCRIPTIONS
+                .iter()
+                .any(|placeholder| existing.eq_ignore_ascii_case(placeholder)) =>
+        {
+            ExistingValue::Invalid
+        }
+        Some(existing) if existing == description.trim() => ExistingValue::Agrees,
+        Some(_) => ExistingValue::Differs,
+    }
+}
+
+/// Updates an existing Metadata object with Google supplemental metadata.
 ///
 /// # Arguments
-/// * `json` - The JSON string containing Google supplemental metadata
+/// * `google_meta` - The parsed Google supplemental metadata
 /// * `metadata` - The existing Metadata object to update
+/// * `options` - How to write the metadata
 ///
 /// # Returns
-/// The updated Metadata object, or an error if parsing fails
+/// The updated Metadata object and how many existing fields were kept or overwritten,
+/// or an error if a timestamp is invalid
 pub fn apply_google_metadata(
-    json: &str,
+    google_meta: &GoogleSupplementalMetadata,
     mut metadata: Metadata,
-) -> Result<Metadata, MetadataError> {
-    let google_meta: GoogleSupplementalMetadata = serde_json::from_str(json).map_err(|e| {
-        let error_msg = e.to_string();
-        // Check if it's an unknown field error
-        if error_msg.contains("unknown field") {
-            // Try to extract the field name from the error message
-            let field = error_msg
-                .split("unknown field `")
-                .nth(1)
-                .and_then(|s| s.split('`').next())
-                .unwrap_or("unknown")
-                .to_string();
-            MetadataError::UnknownField {
-                field,
-                json: json.to_string(),
-            }
-        } else {
-            MetadataError::JsonParseError {
-                message: error_msg,
-                json: json.to_string(),
-            }
-        }
-    })?;
+    options: &MetadataOptions,
+) -> Result<(Metadata, FieldCounts), MetadataError> {
+    let mut counts = FieldCounts::default();
+
+    // Media files without a ModifyDate have not been edited since they were taken, which was
+    // before they were uploaded
+    let sidecar_modified = google_meta.modified_timestamp();
+    let offset = sidecar_modified.map_or(0, |t| google_meta.utc_offset_at(t, options.timezone));
+    let sidecar_is_newer = match (sidecar_modified, exif_modified_timestamp(&metadata, offset)) {
+        (_, None) => true,
+        (None, Some(_)) => false,
+        (Some(sidecar_modified), Some(file_modified)) => sidecar_modified > file_modified,
+    };
 
     // Apply description if present and non-empty
-    if !google_meta.description.is_empty() {
-        metadata.set_tag(ExifTag::ImageDescription(google_meta.description));
+    if !google_meta.description.is_empty()
+        && counts.should_write(
+            options.prefer.description,
+            existing_description(&metadata, &google_meta.description),
+            sidecar_is_newer,
+        )
+    {
+        metadata.set_tag(ExifTag::ImageDescription(google_meta.description.clone()));
     }
 
     // Apply photo taken time if present
-    if let Some(ref photo_time) = google_meta.photo_taken_time {
-        if let Ok(timestamp) = photo_time.timestamp.parse::<i64>() {
-            let datetime = format_exif_datetime(timestamp);
-            metadata.set_tag(ExifTag::DateTimeOriginal(datetime));
+    // EXIF dates are local time, with the offset from UTC in separate tags
+    if let Some(timestamp) = google_meta.taken_timestamp()? {
+        let offset = google_meta.utc_offset_at(timestamp, options.timezone);
+
+        let existing = existing_date(&metadata, timestamp, offset);
+        if counts.should_write(options.prefer.date, existing, sidecar_is_newer) {
+            let datetime = format_exif_datetime(timestamp + offset as i64)?;
+            let offset = format_exif_offset(offset);
+            metadata.set_tag(ExifTag::DateTimeOriginal(datetime.clone()));
+            metadata.set_tag(ExifTag::CreateDate(datetime));
+            metadata.set_tag(ExifTag::OffsetTimeOriginal(offset.clone()));
+            metadata.set_tag(ExifTag::OffsetTimeDigitized(offset.clone()));
+            metadata.set_tag(ExifTag::OffsetTime(offset));
         }
     }
 
     // Apply GPS coordinates if present and valid (non-zero)
-    if let Some(ref geo) = google_meta.geo_data {
-        if geo.latitude != 0.0 || geo.longitude != 0.0 {
+    if let Some(geo) = google_meta.location() {
+        let existing = existing_gps(&metadata, geo);
+        if counts.should_write(options.prefer.gps, existing, sidecar_is_newer) {
             // Convert latitude to EXIF format (degrees, minutes, seconds as rationals)
             let (lat_ref, lat_vals) = decimal_to_dms_exif(geo.latitude, true);
             let (lon_ref, lon_vals) = decimal_to_dms_exif(geo.longitude, false);
@@ -203,70 +673,20 @@ pub fn apply_google_metadata(
         }
     }
 
-    Ok(metadata)
+    Ok((metadata, counts))
 }
 
 /// Formats a Unix timestamp as an EXIF datetime string (YYYY:MM:DD HH:MM:SS)
-fn format_exif_datetime(timestamp: i64) -> String {
-
-    // Convert to a simple date/time representation
-    // Note: This is a simplified implementation; for production use chrono crate
-    let secs_since_epoch = timestamp;
-    let days_since_epoch = secs_since_epoch / 86400;
-    let time_of_day = secs_since_epoch % 86400;
-
-    let hours = time_of_day / 3600;
-    let minutes = (time_of_day % 3600) / 60;
-    let seconds = time_of_day % 60;
-
-    // Simplified date calculation (doesn't account for leap years perfectly)
-    let (year, month, day) = days_to_ymd(days_since_epoch);
-
-    format!(
-        "{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
-        year, month, day, hours, minutes, seconds
-    )
-}
-
-/// Converts days since Unix epoch to year, month, day
-fn days_to_ymd(days: i64) -> (i32, u32, u32) {
-    // Start from 1970-01-01
-    let mut remaining_days = days;
-    let mut year = 1970i32;
-
-    // Find the year
-    loop {
-        let days_in_year = if is_leap_year(year) { 366 } else { 365 };
-        if remaining_days < days_in_year {
-            break;
-        }
-        remaining_days -= days_in_year;
-        year += 1;
-    }
-
-    // Find the month and day
-    let days_in_months: [i64; 12] = if is_leap_year(year) {
-        [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
-    } else {
-        [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31]
-    };
-
-    let mut month = 1u32;
-    for days_in_month in days_in_months.iter() {
-        if remaining_days < *days_in_month {
-            break;
-        }
-        remaining_days -= days_in_month;
-        month += 1;
-    }
-
-    let day = remaining_days as u32 + 1;
-
-    (year, month, day)
-}
-
-fn is_leap_year(year: i32) -> bool {
-    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+fn format_exif_datetime(timestamp: i64) -> Result<String, MetadataError> {
+    DateTime::from_timestamp(timestamp, 0)
+        .filter(|date| (0..=9999).contains(&date.year()))
+        .map(|date| date.format("%Y:%m:%d %H:%M:%S").to_string())
+        .ok_or_else(|| {
+            MetadataError::InvalidTimestamp(format!(
+                "{} cannot be written as an EXIF date",
+                timestamp
+            ))
+        })
 }
 
 /// Converts decimal degrees to EXIF DMS format (degrees, minutes, seconds as rationals)
@@ -299,6 +719,7 @@ fn decimal_to_dms_exif(decimal: f64, is_latitude: bool) -> (String, Vec<uR64>) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     const SAMPLE_JSON: &str = r#"{
         "title": "IMG_8238.JPG",
@@ -335,11 +756,165 @@ mod tests {
         assert!((geo.longitude - 17.3456).abs() < 0.0001);
     }
 
+    #[test]
+    fn test_parse_google_metadata_unknown_fields() {
+        let json = r#"{
+            "title": "IMG_8238.JPG",
+            "photoTakenTime": {"timestamp": "1563032119", "formatted": "", "timezone": "CEST"},
+            "geoData": {"latitude": 46.7234, "longitude": 17.3456, "altitude": 0.0, "accuracy": 5},
+            "people": [{"name": "Anna", "faceId": 1}, {"name": "Bo", "faceId": 2}],
+            "googlePhotosOrigin": {"mobileUpload": {}, "driveSync": {}},
+            "sharedWith": []
+        }"#;
+        let meta = parse_google_metadata(json).unwrap();
+        assert_eq!(meta.taken_timestamp().unwrap(), Some(1563032119));
+        assert!(meta.location().is_some());
+        assert_eq!(
+            meta.unknown_fields(),
+            vec![
+                "geoData.accuracy",
+                "googlePhotosOrigin.driveSync",
+                "people.faceId",
+                "photoTakenTime.timezone",
+                "sharedWith"
+            ]
+        );
+        assert!(matches!(
+            meta.reject_unknown_fields(json),
+            Err(MetadataError::UnknownField { field, .. }) if field == "geoData.accuracy"
+        ));
+
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        assert!(meta.unknown_fields().is_empty());
+        assert!(meta.reject_unknown_fields(SAMPLE_JSON).is_ok());
+    }
+
+    #[test]
+    fn test_parse_album_metadata() {
+        let json = r#"{
+            "title": "Roma & Napoli",
+            "description": "Summer holiday",
+            "access": "protected",
+            "date": {"timestamp": "1563032119", "formatted": "13. jul. 2019, 15.35.19 UTC"}
+        }"#;
+        let album = parse_album_metadata(json).unwrap();
+        assert_eq!(album.title, "Roma & Napoli");
+        assert_eq!(album.description, "Summer holiday");
+        assert_eq!(album.date.unwrap().timestamp, "1563032119");
+
+        assert!(parse_album_metadata(r#"{"description": "No title"}"#).is_err());
+    }
+
+    #[test]
+    fn test_people_names() {
+        let json = r#"{
+            "title": "IMG_8238.JPG",
+            "people": [{"name": "Anna"}, {"name": " Bo "}, {"name": ""}, {}, {"name": "Anna"}]
+        }"#;
+        let meta = parse_google_metadata(json).unwrap();
+        assert_eq!(meta.people_names(), vec!["Anna", "Bo"]);
+
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        assert!(meta.people_names().is_empty());
+    }
+
+    #[test]
+    fn test_taken_or_created_timestamp() {
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        assert_eq!(meta.taken_or_created_timestamp().unwrap(), Some(1563032119));
+
+        let json = r#"{
+            "title": "IMG_8238.JPG",
+            "creationTime": {"timestamp": "1587036746", "formatted": ""},
+            "photoTakenTime": {"timestamp": "", "formatted": ""}
+        }"#;
+        let meta = parse_google_metadata(json).unwrap();
+        assert_eq!(meta.taken_timestamp().unwrap(), None);
+        assert_eq!(meta.taken_or_created_timestamp().unwrap(), Some(1587036746));
+    }
+
+    #[test]
+    fn test_invalid_timestamps() {
+        for timestamp in ["abc", "1563032119.5", "-99999999999", "999999999999"] {
+            let json = format!(
+                r#"{{
+                    "title": "IMG_8238.JPG",
+                    "photoTakenTime": {{"timestamp": "{}", "formatted": ""}}
+                }}"#,
+                timestamp
+            );
+            let meta = parse_google_metadata(&json).unwrap();
+            assert!(matches!(
+                meta.taken_timestamp(),
+                Err(MetadataError::InvalidTimestamp(_))
+            ));
+            assert!(matches!(
+                apply_google_metadata(&meta, Metadata::new(), &MetadataOptions::default()),
+                Err(MetadataError::InvalidTimestamp(_))
+            ));
+        }
+    }
+
     #[test]
     fn test_format_exif_datetime() {
         // 1563032119 = 2019-07-13 15:35:19 UTC
-        let result = format_exif_datetime(1563032119);
-        assert_eq!(result, "2019:07:13 15:35:19");
+        let known_dates = [
+            (1563032119, "2019:07:13 15:35:19"),
+            (0, "1970:01:01 00:00:00"),
+            (-1, "1969:12:31 23:59:59"),
+            (-86399, "1969:12:31 00:00:01"),
+            (-86400, "1969:12:31 00:00:00"),
+            (-176342400, "1964:05:31 00:00:00"),
+            (-2208988800, "1900:01:01 00:00:00"),
+            (-5364662400, "1800:01:01 00:00:00"),
+            (951782400, "2000:02:29 00:00:00"),
+            (4107542400, "2100:03:01 00:00:00"),
+            (253402300799, "9999:12:31 23:59:59"),
+        ];
+        for (timestamp, expected) in known_dates {
+            assert_eq!(format_exif_datetime(timestamp).unwrap(), expected);
+        }
+        assert!(format_exif_datetime(253402300800).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_format_exif_datetime_round_trips(timestamp in MIN_TIMESTAMP..=MAX_TIMESTAMP) {
+            let formatted = format_exif_datetime(timestamp).unwrap();
+            let parsed = NaiveDateTime::parse_from_str(&formatted, "%Y:%m:%d %H:%M:%S").unwrap();
+            prop_assert_eq!(parsed.and_utc().timestamp(), timestamp);
+        }
+
+        #[test]
+        fn prop_format_exif_datetime_time_of_day(timestamp in MIN_TIMESTAMP..=MAX_TIMESTAMP) {
+            // Negative timestamps count back from midnight, never giving negative hours
+            let seconds = timestamp.rem_euclid(86400);
+            let expected = format!(
+                "{:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds % 3600 / 60,
+                seconds % 60
+            );
+            let formatted = format_exif_datetime(timestamp).unwrap();
+            prop_assert_eq!(&formatted[11..], expected.as_str());
+        }
+
+        #[test]
+        fn prop_format_exif_datetime_next_day(day in -60000i64..2900000) {
+            // Midnight of consecutive days are consecutive calendar dates
+            let today = NaiveDateTime::parse_from_str(
+                &format_exif_datetime(day * 86400).unwrap(),
+                "%Y:%m:%d %H:%M:%S",
+            )
+            .unwrap();
+            let tomorrow = NaiveDateTime::parse_from_str(
+                &format_exif_datetime((day + 1) * 86400).unwrap(),
+                "%Y:%m:%d %H:%M:%S",
+            )
+            .unwrap();
+            prop_assert_eq!(today.date().succ_opt(), Some(tomorrow.date()));
+            prop_assert_eq!(tomorrow.time(), chrono::NaiveTime::MIN);
+        }
     }
 
     #[test]
@@ -355,8 +930,215 @@ mod tests {
 
     #[test]
     fn test_apply_google_metadata() {
-        let metadata = Metadata::new();
-        let result = apply_google_metadata(SAMPLE_JSON, metadata);
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let result = apply_google_metadata(&meta, Metadata::new(), &MetadataOptions::default());
         assert!(result.is_ok());
     }
+
+    fn string_tag(metadata: &Metadata, tag: ExifTag) -> Option<String> {
+        match metadata.get_tag(&tag).next()? {
+            ExifTag::DateTimeOriginal(s)
+            | ExifTag::CreateDate(s)
+            | ExifTag::OffsetTimeOriginal(s)
+            | ExifTag::OffsetTime(s) => Some(s.trim_end_matches('\0').to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_apply_google_metadata_local_time() {
+        // Taken at Lake Balaton in summer, so CEST
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let (metadata, _) =
+            apply_google_metadata(&meta, Metadata::new(), &MetadataOptions::default()).unwrap();
+        assert_eq!(
+            string_tag(&metadata, ExifTag::DateTimeOriginal(String::new())).as_deref(),
+            Some("2019:07:13 17:35:19")
+        );
+        assert_eq!(
+            string_tag(&metadata, ExifTag::CreateDate(String::new())).as_deref(),
+            Some("2019:07:13 17:35:19")
+        );
+        assert_eq!(
+            string_tag(&metadata, ExifTag::OffsetTimeOriginal(String::new())).as_deref(),
+            Some("+02:00")
+        );
+        assert_eq!(
+            string_tag(&metadata, ExifTag::OffsetTime(String::new())).as_deref(),
+            Some("+02:00")
+        );
+    }
+
+    #[test]
+    fn test_apply_google_metadata_fallback_timezone() {
+        let json = r#"{
+            "title": "IMG_8238.JPG",
+            "photoTakenTime": {"timestamp": "1563032119", "formatted": ""},
+            "geoData": {"latitude": 0.0, "longitude": 0.0, "altitude": 0.0}
+        }"#;
+
+        // Without a location or a timezone the time stays in UTC
+        let meta = parse_google_metadata(json).unwrap();
+        let (metadata, _) =
+            apply_google_metadata(&meta, Metadata::new(), &MetadataOptions::default()).unwrap();
+        assert_eq!(
+            string_tag(&metadata, ExifTag::DateTimeOriginal(String::new())).as_deref(),
+            Some("2019:07:13 15:35:19")
+        );
+        assert_eq!(
+            string_tag(&metadata, ExifTag::OffsetTimeOriginal(String::new())).as_deref(),
+            Some("+00:00")
+        );
+
+        let options = MetadataOptions {
+            timezone: Some("America/New_York".parse().unwrap()),
+            ..Default::default()
+        };
+        let (metadata, _) = apply_google_metadata(&meta, Metadata::new(), &options).unwrap();
+        assert_eq!(
+            string_tag(&metadata, ExifTag::DateTimeOriginal(String::new())).as_deref(),
+            Some("2019:07:13 11:35:19")
+        );
+        assert_eq!(
+            string_tag(&metadata, ExifTag::OffsetTimeOriginal(String::new())).as_deref(),
+            Some("-04:00")
+        );
+    }
+
+    #[test]
+    fn test_exif_capture_time() {
+        let mut metadata = Metadata::new();
+        assert_eq!(exif_capture_time(&metadata), None);
+
+        metadata.set_tag(ExifTag::DateTimeOriginal("2019:07:13 09:00:00\0".to_string()));
+        assert_eq!(
+            exif_capture_time(&metadata).map(|t| t.to_string()).as_deref(),
+            Some("2019-07-13 09:00:00")
+        );
+
+        metadata.set_tag(ExifTag::DateTimeOriginal("0000:00:00 00:00:00".to_string()));
+        assert_eq!(exif_capture_time(&metadata), None);
+
+        // The same years are accepted as in sidecars
+        metadata.set_tag(ExifTag::DateTimeOriginal("1850:01:01 00:00:00".to_string()));
+        assert!(exif_capture_time(&metadata).is_some());
+        metadata.set_tag(ExifTag::DateTimeOriginal("1799:12:31 23:59:59".to_string()));
+        assert_eq!(exif_capture_time(&metadata), None);
+    }
+
+    #[test]
+    fn test_apply_google_metadata_prefer_existing() {
+        let mut existing = Metadata::new();
+        existing.set_tag(ExifTag::DateTimeOriginal("2019:07:13 09:00:00".to_string()));
+        existing.set_tag(ExifTag::ImageDescription("Family trip".to_string()));
+
+        let options = MetadataOptions {
+            prefer: FieldPreferences::all(Preference::Existing),
+            ..Default::default()
+        };
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let (metadata, counts) = apply_google_metadata(&meta, existing, &options).unwrap();
+        assert_eq!(
+            string_tag(&metadata, ExifTag::DateTimeOriginal(String::new())).as_deref(),
+            Some("2019:07:13 09:00:00")
+        );
+        assert_eq!(
+            existing_string(&metadata, ExifTag::ImageDescription(String::new())).as_deref(),
+            Some("Family trip")
+        );
+        // GPS was missing, so it is filled in without being counted
+        assert!(metadata.get_tag(&ExifTag::GPSLatitude(Vec::new())).next().is_some());
+        assert_eq!(counts, FieldCounts { kept: 2, overwritten: 0 });
+    }
+
+    #[test]
+    fn test_apply_google_metadata_prefer_newest() {
+        // The date agrees with the sidecar, the description was edited in Google Photos
+        let mut existing = Metadata::new();
+        existing.set_tag(ExifTag::DateTimeOriginal("2019:07:13 17:35:19".to_string()));
+        existing.set_tag(ExifTag::OffsetTimeOriginal("+02:00".to_string()));
+        existing.set_tag(ExifTag::ImageDescription("Sunset".to_string()));
+
+        let options = MetadataOptions {
+            prefer: FieldPreferences::all(Preference::Newest),
+            ..Default::default()
+        };
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let (metadata, counts) = apply_google_metadata(&meta, existing, &options).unwrap();
+        assert_eq!(
+            existing_string(&metadata, ExifTag::ImageDescription(String::new())).as_deref(),
+            Some("A beautiful sunset")
+        );
+        assert_eq!(counts, FieldCounts { kept: 1, overwritten: 1 });
+    }
+
+    #[test]
+    fn test_apply_google_metadata_prefer_newest_keeps_later_edits() {
+        // The description was edited in the file after it was uploaded on 2020-04-16
+        let mut existing = Metadata::new();
+        existing.set_tag(ExifTag::ImageDescription("Sunset".to_string()));
+        existing.set_tag(ExifTag::ModifyDate("2021:01:01 12:00:00".to_string()));
+
+        let options = MetadataOptions {
+            prefer: FieldPreferences::all(Preference::Newest),
+            ..Default::default()
+        };
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let (metadata, counts) =
+            apply_google_metadata(&meta, existing.clone(), &options).unwrap();
+        assert_eq!(
+            existing_string(&metadata, ExifTag::ImageDescription(String::new())).as_deref(),
+            Some("Sunset")
+        );
+        assert_eq!(counts, FieldCounts { kept: 1, overwritten: 0 });
+
+        // Unless the sidecar was edited in Google Photos later still
+        let json = SAMPLE_JSON.replace(
+            r#""url""#,
+            r#""photoLastModifiedTime": {"timestamp": "1640995200", "formatted": ""}, "url""#,
+        );
+        let meta = parse_google_metadata(&json).unwrap();
+        let (metadata, counts) = apply_google_metadata(&meta, existing, &options).unwrap();
+        assert_eq!(
+            existing_string(&metadata, ExifTag::ImageDescription(String::new())).as_deref(),
+            Some("A beautiful sunset")
+        );
+        assert_eq!(counts, FieldCounts { kept: 0, overwritten: 1 });
+    }
+
+    #[test]
+    fn test_apply_google_metadata_counts_agreeing_values_as_kept() {
+        let mut existing = Metadata::new();
+        existing.set_tag(ExifTag::DateTimeOriginal("2019:07:13 17:35:19".to_string()));
+        existing.set_tag(ExifTag::OffsetTimeOriginal("+02:00".to_string()));
+        existing.set_tag(ExifTag::ImageDescription("Sunset".to_string()));
+
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let options = MetadataOptions::default();
+        let (_, counts) = apply_google_metadata(&meta, existing, &options).unwrap();
+        assert_eq!(counts, FieldCounts { kept: 1, overwritten: 1 });
+    }
+
+    #[test]
+    fn test_apply_google_metadata_replaces_invalid_values() {
+        let mut existing = Metadata::new();
+        existing.set_tag(ExifTag::DateTimeOriginal("0000:00:00 00:00:00".to_string()));
+        existing.set_tag(ExifTag::ImageDescription("OLYMPUS DIGITAL CAMERA".to_string()));
+
+        let options = MetadataOptions {
+            prefer: FieldPreferences::all(Preference::Existing),
+            ..Default::default()
+        };
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let (metadata, counts) = apply_google_metadata(&meta, existing, &options).unwrap();
+        assert_eq!(
+            string_tag(&metadata, ExifTag::DateTimeOriginal(String::new())).as_deref(),
+            Some("2019:07:13 17:35:19")
+        );
+        assert_eq!(
+            existing_string(&metadata, ExifTag::ImageDescription(String::new())).as_deref(),
+            Some("A beautiful sunset")
+        );
+        assert_eq!(counts, FieldCounts { kept: 0, overwritten: 2 });
+    }
 }
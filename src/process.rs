@@ -1,15 +1,38 @@
-use crate::archive::{ArchiveFile, Takeout};
-use crate::metadata::{apply_google_metadata, MetadataError};
-use flate2::read::GzDecoder;
+use crate::archive::{
+    claim_title_metadata, is_album_metadata_path, ArchiveFile, MatchKind, MetadataLookup,
+    MetadataMatch, SidecarTitles, Takeout,
+};
+use crate::atomic::{remove_temp_files, temp_path, write_atomically};
+use crate::collision::{Collision, CollisionPolicy, OutputClaims, Resolution};
+use crate::dedup::{
+    hash_contents, link_file, write_album_manifest, ContentHash, DedupMode, ALBUM_MANIFEST_FILE,
+};
+use crate::metadata::{
+    apply_google_metadata, exif_capture_time, parse_album_metadata, parse_google_metadata,
+    parse_title, FieldCounts, GoogleSupplementalMetadata, MetadataError, MetadataOptions,
+};
+use crate::journal::{hash_data, hash_metadata, read_journal, Journal, JournalEntry};
+use crate::layout::{Layout, LayoutFields};
+use crate::source::{ArchiveSource, SourceError, SourceReader};
+use crate::sync::lock;
+use crate::video::apply_google_metadata_to_video;
+use crate::xmp::{
+    embed_xmp_in_jpeg, google_metadata_to_xmp, google_metadata_to_xmp_extras, keywords_to_xmp,
+    xmp_sidecar_path,
+};
+use chrono::{DateTime, NaiveDate};
 use indicatif::{ProgressBar, ProgressStyle};
 use little_exif::filetype::FileExtension;
 use little_exif::metadata::Metadata;
-use std::collections::{HashMap, HashSet};
-use std::fs::{self, File};
-use std::io::{BufReader, Read, Write};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use tar::Archive as TarArchive;
-use zip::ZipArchive;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TryRecvError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Error type for processing operations
 #[derive(Debug)]
@@ -18,6 +41,8 @@ pub enum ProcessError {
     ArchiveError(String),
     MetadataError(MetadataError),
     ExifError(String),
+    /// Options that cannot be used together
+    InvalidOptions(String),
 }
 
 impl std::fmt::Display for ProcessError {
@@ -27,6 +52,7 @@ impl std::fmt::Display for ProcessError {
             ProcessError::ArchiveError(msg) => write!(f, "Archive error: {}", msg),
             ProcessError::MetadataError(e) => write!(f, "Metadata error: {}", e),
             ProcessError::ExifError(msg) => write!(f, "EXIF error: {}", msg),
+            ProcessError::InvalidOptions(msg) => write!(f, "Invalid options: {}", msg),
         }
     }
 }
@@ -39,6 +65,17 @@ impl From<MetadataError> for ProcessError {
     }
 }
 
+impl From<SourceError> for ProcessError {
+    fn from(e: SourceError) -> Self {
+        match e {
+            SourceError::IoError(msg) => ProcessError::IoError(msg),
+            SourceError::ArchiveError(msg) | SourceError::Unsupported(msg) => {
+                ProcessError::ArchiveError(msg)
+            }
+        }
+    }
+}
+
 /// Statistics for the processing operation
 #[derive(Debug, Default)]
 pub struct ProcessStats {
@@ -50,17 +87,164 @@ pub struct ProcessStats {
     pub images_processed_with_metadata: usize,
     pub images_processed_without_metadata: usize,
     pub videos_copied: usize,
+    /// Edited media that were given the metadata of their original
+    pub edited_matched: usize,
+    /// Live Photo and Motion Photo videos that were given the metadata of their still
+    pub companions_matched: usize,
+    /// Existing date, GPS and description fields left as they were
+    pub fields_kept: usize,
+    /// Existing date, GPS and description fields replaced with sidecar values
+    pub fields_overwritten: usize,
+    /// Media paired with a sidecar only through the sidecar's title
+    pub title_matched: usize,
+    /// Media left without metadata because several sidecars carry its name as their title, or
+    /// the only one belongs to another media file
+    pub title_ambiguous: usize,
+    /// Media in the trash in Google Photos, whether skipped, included or kept apart
+    pub trashed: usize,
+    /// Archived media (not also trashed), whether included or kept apart
+    pub archived: usize,
+    /// Sidecar fields this tool does not know about, with how many sidecars had them
+    pub unknown_fields: BTreeMap<String, usize>,
+    /// Media identical to another file of the takeout, linked to or listed with it instead of
+    /// being written again
+    pub duplicates: usize,
+    /// Media files mapped to an output path already taken by another, and how each was resolved
+    pub collisions: Vec<Collision>,
+    /// Media left as it was because an earlier run had written it completely
+    pub resumed: usize,
+    /// Media written by an update that the earlier run had not written
+    pub added: usize,
+    /// Media written again by an update because it or its sidecar changed
+    pub changed: usize,
+    /// Media left as it was by an update because its sidecar did not change
+    pub unchanged: usize,
     pub errors: usize,
 }
 
-struct ArchiveCache {
-    zip_archives: HashMap<PathBuf, ZipArchive<BufReader<File>>>,
+/// Where metadata from sidecars is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MetadataMode {
+    /// Into the media files themselves, for formats that support it
+    #[default]
+    Embed,
+    /// Into an XMP sidecar next to each media file, leaving the media byte-for-byte intact
+    Xmp,
+}
+
+/// Output folder for trashed media, when kept apart from the albums
+pub const TRASHED_DIR: &str = "Trash";
+
+/// Output folder for archived media, when kept apart from the albums
+pub const ARCHIVED_DIR: &str = "Archive";
+
+/// What to do with media that is in the trash in Google Photos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TrashedMode {
+    /// Leave it out of the output
+    Skip,
+    /// Write it to its album like any other media
+    #[default]
+    Include,
+    /// Write it to its album inside a separate Trash folder
+    Separate,
 }
 
-impl ArchiveCache {
+/// What to do with media that is archived in Google Photos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ArchivedMode {
+    /// Write it to its album like any other media
+    #[default]
+    Include,
+    /// Write it to its album inside a separate Archive folder
+    Separate,
+}
+
+/// Options controlling how a takeout is processed
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    /// Only report what would be done
+    pub dry_run: bool,
+    /// Print per-file progress
+    pub debug: bool,
+    /// Show a progress bar
+    pub show_progress: bool,
+    /// Number of worker threads processing media files
+    pub jobs: usize,
+    /// Set the modification and access times of output files to the capture time
+    pub set_file_times: bool,
+    /// Whether metadata is written into the media or into XMP sidecars
+    pub metadata_mode: MetadataMode,
+    /// How metadata is written into images
+    pub metadata: MetadataOptions,
+    /// Treat sidecars with unknown fields as errors instead of ignoring those fields
+    pub strict: bool,
+    /// What to do with trashed media
+    pub trashed: TrashedMode,
+    /// What to do with archived media
+    pub archived: ArchivedMode,
+    /// Add the title of its album as a keyword to each media file
+    pub album_keywords: bool,
+    /// Whether identical media in several folders is written once
+    pub dedup: DedupMode,
+    /// Where media is written below the output directory
+    pub layout: Layout,
+    /// What to do when several media files map to the same output path
+    pub on_collision: CollisionPolicy,
+    /// Skip media that the journal of an earlier run says was written, if it is still intact
+    pub resume: bool,
+    /// Only write media that the journal of an earlier run does not have, or whose sidecar
+    /// changed since
+    pub update: bool,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            debug: false,
+            show_progress: true,
+            jobs: 1,
+            set_file_times: true,
+            metadata_mode: MetadataMode::Embed,
+            metadata: MetadataOptions::default(),
+            strict: false,
+            trashed: TrashedMode::Include,
+            archived: ArchivedMode::Include,
+            album_keywords: false,
+            dedup: DedupMode::Off,
+            layout: Layout::default(),
+            on_collision: CollisionPolicy::Suffix,
+            resume: false,
+            update: false,
+        }
+    }
+}
+
+impl ProcessOptions {
+    /// Checks that the options can be used together
+    pub fn validate(&self) -> Result<(), ProcessError> {
+        // A deduplicated file is shared by all its albums, so it cannot carry each one's title
+        if self.album_keywords && self.dedup != DedupMode::Off {
+            return Err(ProcessError::InvalidOptions(
+                "album keywords cannot be combined with deduplication, as media in several \
+                 albums is only written once"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Open readers for random-access sources, keyed by source path
+struct ArchiveCache<'a> {
+    readers: HashMap<PathBuf, Box<dyn SourceReader + 'a>>,
+}
+
+impl<'a> ArchiveCache<'a> {
     fn new() -> Self {
         Self {
-            zip_archives: HashMap::new(),
+            readers: HashMap::new(),
         }
     }
 }
@@ -70,11 +254,15 @@ const IMAGE_EXTENSIONS: &[&str] = &[
     ".jpg", ".jpeg", ".png", ".gif", ".webp", ".heic", ".heif", ".tiff", ".tif", ".bmp",
 ];
 
-/// Video file extensions (we copy but don't modify EXIF)
+/// Video file extensions (we don't modify EXIF).
+/// ".mp" is the video half of a Pixel Motion Photo.
 const VIDEO_EXTENSIONS: &[&str] = &[
-    ".mp4", ".mov", ".avi", ".mkv", ".webm", ".m4v", ".3gp", ".wmv",
+    ".mp4", ".mov", ".avi", ".mkv", ".webm", ".m4v", ".3gp", ".wmv", ".mp",
 ];
 
+/// Video file extensions using the MP4/QuickTime container, which we write metadata into
+const MP4_VIDEO_EXTENSIONS: &[&str] = &[".mp4", ".mov", ".m4v", ".3gp", ".mp"];
+
 /// Check if a file is an image based on extension
 fn is_image_file(path: &str) -> bool {
     let lower = path.to_lowercase();
@@ -107,41 +295,28 @@ fn extract_album_path(archive_path: &str, photo_path_prefix: &str) -> String {
     }
 }
 
-fn read_zip_file_cached(cache: &mut ArchiveCache, file: &ArchiveFile) -> Result<Vec<u8>, ProcessError> {
-    let archive_name = file
-        .source_archive
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("");
-
-    if archive_name.ends_with(".zip") {
-        if !cache.zip_archives.contains_key(&file.source_archive) {
-            let archive_file = File::open(&file.source_archive)
-                .map_err(|e| ProcessError::IoError(format!("Failed to open archive: {}", e)))?;
-            let reader = BufReader::new(archive_file);
-            let archive = ZipArchive::new(reader)
-                .map_err(|e| ProcessError::ArchiveError(format!("Failed to read zip: {}", e)))?;
-            cache.zip_archives.insert(file.source_archive.clone(), archive);
-        }
-
-        let archive = cache
-            .zip_archives
-            .get_mut(&file.source_archive)
-            .ok_or_else(|| ProcessError::ArchiveError("Zip cache missing".to_string()))?;
-        let mut entry = archive
-            .by_index(file.index)
-            .map_err(|e| ProcessError::ArchiveError(format!("Failed to read entry: {}", e)))?;
-        let mut contents = Vec::new();
-        entry
-            .read_to_end(&mut contents)
-            .map_err(|e| ProcessError::IoError(format!("Failed to read file contents: {}", e)))?;
-        Ok(contents)
-    } else {
-        Err(ProcessError::ArchiveError(format!(
-            "Unsupported archive format: {}",
-            archive_name
-        )))
+fn read_file_cached<'a>(
+    takeout: &'a Takeout,
+    cache: &mut ArchiveCache<'a>,
+    file: &ArchiveFile,
+) -> Result<Vec<u8>, ProcessError> {
+    if !cache.readers.contains_key(&file.source_archive) {
+        let source = takeout.source(&file.source_archive).ok_or_else(|| {
+            ProcessError::ArchiveError(format!(
+                "Unknown source: {}",
+                file.source_archive.display()
+            ))
+        })?;
+        cache
+            .readers
+            .insert(file.source_archive.clone(), source.open_reader()?);
     }
+
+    let reader = cache
+        .readers
+        .get_mut(&file.source_archive)
+        .ok_or_else(|| ProcessError::ArchiveError("Reader cache missing".to_string()))?;
+    Ok(reader.read_entry(file.index)?)
 }
 
 /// Get the FileExtension for a file based on its path
@@ -165,20 +340,21 @@ fn get_file_extension(path: &str) -> FileExtension {
     }
 }
 
-/// Process a single image file: read it, apply metadata, write to output
+/// Process a single image file: read it and apply metadata in memory.
+/// Returns the image to write, and how existing fields were merged if metadata was applied.
 fn process_image_data(
     image_path: &str,
     image_data: Vec<u8>,
-    metadata_json: Option<&str>,
-    output_path: &Path,
+    google_meta: Option<&GoogleSupplementalMetadata>,
+    options: &MetadataOptions,
     debug: bool,
-) -> Result<bool, ProcessError> {
+) -> Result<(Vec<u8>, Option<FieldCounts>), ProcessError> {
 
     // Determine file extension for little_exif
     let file_ext = get_file_extension(image_path);
 
     // Try to read existing EXIF metadata from the image
-    let metadata = match Metadata::new_from_vec(&image_data, file_ext.clone()) {
+    let metadata = match Metadata::new_from_vec(&image_data, file_ext) {
         Ok(m) => m,
         Err(_) => {
             // No existing metadata, create empty
@@ -187,446 +363,1463 @@ fn process_image_data(
     };
 
     // Apply Google metadata if available
-    let final_metadata = if let Some(json_str) = metadata_json {
+    let (final_metadata, counts) = if let Some(google_meta) = google_meta {
         if debug {
             println!("    Applying metadata from JSON");
         }
 
-        apply_google_metadata(json_str, metadata)?
+        let (metadata, counts) = apply_google_metadata(google_meta, metadata, options)?;
+        (metadata, Some(counts))
     } else {
-        metadata
+        (metadata, None)
     };
 
-    // Create parent directories
-    if let Some(parent) = output_path.parent() {
-        fs::create_dir_all(parent)
-            .map_err(|e| ProcessError::IoError(format!("Failed to create directory: {}", e)))?;
+    // Write the metadata into a copy of the image, keeping the original if that fails.
+    // little_exif panics on some malformed images, which only fails this file.
+    let mut updated = image_data.clone();
+    let written = panic::catch_unwind(AssertUnwindSafe(|| {
+        final_metadata.write_to_vec(&mut updated, file_ext)
+    }));
+    match written {
+        Ok(Ok(())) => Ok((updated, counts)),
+        Ok(Err(e)) => {
+            if debug {
+                // Don't fail the whole process, just note the warning
+                println!("    Warning: Could not write EXIF metadata: {}", e);
+            }
+            Ok((image_data, counts))
+        }
+        Err(payload) => {
+            let reason = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(ProcessError::ExifError(format!("Writing EXIF metadata panicked: {}", reason)))
+        }
     }
+}
 
-    // Write the image with updated metadata
-    // First write the original image data
-    let mut output_file = File::create(output_path)
-        .map_err(|e| ProcessError::IoError(format!("Failed to create output file: {}", e)))?;
-    output_file
-        .write_all(&image_data)
-        .map_err(|e| ProcessError::IoError(format!("Failed to write image data: {}", e)))?;
-    drop(output_file);
+/// Write a media file and the XMP sidecar next to it, if any. The sidecar is written first, so
+/// a media file in the output always has its complete sidecar.
+fn write_output(
+    output_path: &Path,
+    data: &[u8],
+    xmp_sidecar: Option<&str>,
+    modified: Option<SystemTime>,
+) -> Result<(), ProcessError> {
+    if let Some(xmp) = xmp_sidecar {
+        write_atomically(&xmp_sidecar_path(output_path), xmp.as_bytes(), None)
+            .map_err(|e| ProcessError::IoError(format!("Failed to write XMP sidecar: {}", e)))?;
+    }
+    write_atomically(output_path, data, modified)
+        .map_err(|e| ProcessError::IoError(format!("Failed to write output file: {}", e)))?;
 
-    // Then write the metadata to the file
-    if let Err(e) = final_metadata.write_to_file(output_path) {
-        if debug {
-            println!("    Warning: Could not write EXIF metadata: {}", e);
-        }
-        // Don't fail the whole process, just note the warning
+    // A file replaced by a larger or updated one may leave a sidecar that is no longer its own
+    if xmp_sidecar.is_none() {
+        let _ = fs::remove_file(xmp_sidecar_path(output_path));
     }
+    Ok(())
+}
 
-    Ok(metadata_json.is_some())
+/// Returns the XMP sidecar carrying all of a media file's metadata, which works for every
+/// file type, so the media itself can be copied verbatim. Media without a sidecar only gets
+/// one for its `keywords`.
+fn full_xmp_sidecar(
+    google_meta: Option<&GoogleSupplementalMetadata>,
+    keywords: &[String],
+    options: &MetadataOptions,
+    debug: bool,
+) -> Result<Option<String>, ProcessError> {
+    let xmp = match google_meta {
+        Some(google_meta) => google_metadata_to_xmp(google_meta, keywords, options)?,
+        None => match keywords_to_xmp(keywords) {
+            Some(xmp) => xmp,
+            None => return Ok(None),
+        },
+    };
+    if debug {
+        println!("    Writing XMP sidecar");
+    }
+    Ok(Some(xmp))
 }
 
-/// Copy a file without modification (for videos, etc.)
-fn copy_file_data(data: Vec<u8>, output_path: &Path, _debug: bool) -> Result<(), ProcessError> {
+/// Adds the metadata that EXIF has no tags for, such as people and `keywords`, as XMP. It is
+/// embedded into JPEGs where possible, and otherwise returned to be written to an XMP sidecar.
+fn add_xmp_extras(
+    output_path: &Path,
+    data: Vec<u8>,
+    google_meta: Option<&GoogleSupplementalMetadata>,
+    keywords: &[String],
+    options: &MetadataOptions,
+    debug: bool,
+) -> Result<(Vec<u8>, Option<String>), ProcessError> {
+    let xmp = match google_meta {
+        Some(google_meta) => google_metadata_to_xmp_extras(google_meta, keywords, options),
+        None => keywords_to_xmp(keywords),
+    };
+    let Some(xmp) = xmp else {
+        return Ok((data, None));
+    };
 
-    if let Some(parent) = output_path.parent() {
-        fs::create_dir_all(parent)
-            .map_err(|e| ProcessError::IoError(format!("Failed to create directory: {}", e)))?;
+    let lower = output_path.to_string_lossy().to_lowercase();
+    if lower.ends_with(".jpg") || lower.ends_with(".jpeg") {
+        match embed_xmp_in_jpeg(&data, &xmp) {
+            Ok(updated) => return Ok((updated, None)),
+            Err(e) => {
+                if debug {
+                    println!("    Writing XMP sidecar instead: {}", e);
+                }
+            }
+        }
     }
 
-    let mut output_file = File::create(output_path)
-        .map_err(|e| ProcessError::IoError(format!("Failed to create output file: {}", e)))?;
-    output_file
-        .write_all(&data)
-        .map_err(|e| ProcessError::IoError(format!("Failed to write file data: {}", e)))?;
+    Ok((data, Some(xmp)))
+}
 
-    Ok(())
+/// Process a video: write capture time and location into MP4/QuickTime containers
+/// in memory and leave other formats as-is
+fn process_video_data(
+    video_path: &str,
+    data: Vec<u8>,
+    google_meta: Option<&GoogleSupplementalMetadata>,
+    debug: bool,
+) -> Result<(Vec<u8>, bool), ProcessError> {
+    let Some(google_meta) = google_meta else {
+        return Ok((data, false));
+    };
+    // Report unusable capture times as errors, like for images, rather than copying undated
+    google_meta.taken_timestamp()?;
+
+    let lower = video_path.to_lowercase();
+    let data = if MP4_VIDEO_EXTENSIONS.iter().any(|ext| lower.ends_with(ext)) {
+        if debug {
+            println!("    Applying metadata from JSON");
+        }
+        match apply_google_metadata_to_video(&data, google_meta) {
+            Ok(updated) => updated,
+            Err(e) => {
+                // Don't fail the whole process, just keep the original video
+                if debug {
+                    println!("    Warning: Could not write video metadata: {}", e);
+                }
+                data
+            }
+        }
+    } else {
+        data
+    };
+
+    Ok((data, true))
 }
 
-fn is_zip_archive(path: &Path) -> bool {
-    path.file_name()
-        .and_then(|n| n.to_str())
-        .map(|n| n.ends_with(".zip"))
-        .unwrap_or(false)
+/// Returns the time to date an output file with: when the media was taken, falling back to
+/// when it was uploaded. Works for every file type, whether or not the metadata could be
+/// written into the file itself.
+fn file_time(google_meta: &GoogleSupplementalMetadata) -> Result<Option<SystemTime>, ProcessError> {
+    let Some(timestamp) = google_meta.taken_or_created_timestamp()? else {
+        return Ok(None);
+    };
+
+    let offset = Duration::from_secs(timestamp.unsigned_abs());
+    let time = if timestamp >= 0 {
+        UNIX_EPOCH.checked_add(offset)
+    } else {
+        UNIX_EPOCH.checked_sub(offset)
+    }
+    .ok_or_else(|| ProcessError::IoError(format!("File time out of range: {}", timestamp)))?;
+    Ok(Some(time))
 }
 
-fn is_tar_gz_archive(path: &Path) -> bool {
-    path.file_name()
-        .and_then(|n| n.to_str())
-        .map(|n| n.ends_with(".tar.gz"))
-        .unwrap_or(false)
+/// Returns the date at a Unix timestamp in a timezone `offset` seconds from UTC
+fn local_date(timestamp: i64, offset: i32) -> Option<NaiveDate> {
+    let local = timestamp.checked_add(offset.into())?;
+    DateTime::from_timestamp(local, 0).map(|datetime| datetime.date_naive())
 }
 
-fn build_metadata_cache(
+/// Returns the local date an image was taken according to its EXIF
+fn exif_date(image_path: &str, image_data: &Vec<u8>) -> Option<NaiveDate> {
+    let metadata = Metadata::new_from_vec(image_data, get_file_extension(image_path)).ok()?;
+    exif_capture_time(&metadata).map(|datetime| datetime.date())
+}
+
+/// Reads when images were taken from their EXIF, keyed by archive path. Images in sequential
+/// sources are read by streaming each of those sources once.
+fn read_exif_dates(
     takeout: &Takeout,
-    archive_cache: &mut ArchiveCache,
+    images: &[&ArchiveFile],
+) -> Result<HashMap<String, NaiveDate>, ProcessError> {
+    let mut dates = HashMap::new();
+    let mut archive_cache = ArchiveCache::new();
+    let mut sequential_images_by_source: HashMap<&Path, HashSet<&str>> = HashMap::new();
+
+    for image in images {
+        if is_random_access(takeout, image) {
+            let data = read_file_cached(takeout, &mut archive_cache, image)?;
+            if let Some(date) = exif_date(&image.archive_path, &data) {
+                dates.insert(image.archive_path.clone(), date);
+            }
+        } else {
+            sequential_images_by_source
+                .entry(image.source_archive.as_path())
+                .or_default()
+                .insert(image.archive_path.as_str());
+        }
+    }
+
+    for source in takeout.sources() {
+        let Some(wanted_paths) = sequential_images_by_source.get(source.path()) else {
+            continue;
+        };
+
+        source.for_each_entry(&mut |entry, reader| {
+            if wanted_paths.contains(entry.path.as_str()) {
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data)?;
+                if let Some(date) = exif_date(&entry.path, &data) {
+                    dates.insert(entry.path.clone(), date);
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(dates)
+}
+
+/// Returns true if the file lives in a source that can be read in any order
+fn is_random_access(takeout: &Takeout, file: &ArchiveFile) -> bool {
+    takeout
+        .source(&file.source_archive)
+        .is_some_and(|s| s.supports_random_access())
+}
+
+fn build_metadata_cache<'a>(
+    takeout: &'a Takeout,
+    archive_cache: &mut ArchiveCache<'a>,
 ) -> Result<HashMap<String, String>, ProcessError> {
     let mut metadata_map = HashMap::new();
-    let mut tar_metadata_by_archive: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    let mut sequential_metadata_by_source: HashMap<PathBuf, HashSet<String>> = HashMap::new();
 
-    for meta in takeout.supplemental_metadata_files() {
-        if is_tar_gz_archive(&meta.source_archive) {
-            tar_metadata_by_archive
-                .entry(meta.source_archive.clone())
-                .or_default()
-                .insert(meta.archive_path.clone());
-        } else if is_zip_archive(&meta.source_archive) {
-            let json_data = read_zip_file_cached(archive_cache, meta)?;
+    let metadata_files = takeout
+        .supplemental_metadata_files()
+        .chain(takeout.album_metadata_files());
+    for meta in metadata_files {
+        if is_random_access(takeout, meta) {
+            let json_data = read_file_cached(takeout, archive_cache, meta)?;
             let json_str = String::from_utf8(json_data)
                 .map_err(|e| ProcessError::IoError(format!("Invalid UTF-8 in metadata: {}", e)))?;
             metadata_map.insert(meta.archive_path.clone(), json_str);
+        } else {
+            sequential_metadata_by_source
+                .entry(meta.source_archive.clone())
+                .or_default()
+                .insert(meta.archive_path.clone());
         }
     }
 
-    for (archive_path, wanted_paths) in tar_metadata_by_archive {
-        let file = File::open(&archive_path)
-            .map_err(|e| ProcessError::IoError(format!("Failed to open archive: {}", e)))?;
-        let reader = BufReader::new(file);
-        let decoder = GzDecoder::new(reader);
-        let mut archive = TarArchive::new(decoder);
+    for source in takeout.sources() {
+        let Some(wanted_paths) = sequential_metadata_by_source.get(source.path()) else {
+            continue;
+        };
 
-        let entries = archive
-            .entries()
-            .map_err(|e| ProcessError::ArchiveError(format!("Failed to read tar entries: {}", e)))?;
+        source.for_each_entry(&mut |entry, reader| {
+            if wanted_paths.contains(&entry.path) {
+                let mut contents = String::new();
+                reader.read_to_string(&mut contents)?;
+                metadata_map.insert(entry.path.clone(), contents);
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(metadata_map)
+}
+
+/// An album described by a metadata file
+struct Album {
+    /// The album title as shown in Google Photos
+    title: String,
+    /// Where the album's media is written, relative to the output directory
+    output_path: String,
+}
 
-        for entry in entries {
-            let mut entry = entry
-                .map_err(|e| ProcessError::ArchiveError(format!("Failed to read entry: {}", e)))?;
-            if !entry.header().entry_type().is_file() {
+/// Replaces characters that cannot appear in file names, returning `None` if nothing usable
+/// is left
+fn sanitize_folder_name(name: &str) -> Option<String> {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows drops trailing dots and spaces from names
+    let sanitized = sanitized.trim().trim_end_matches('.').to_string();
+    if sanitized.is_empty() {
+        None
+    } else {
+        Some(sanitized)
+    }
+}
+
+/// Reads the metadata files of albums, naming each album's output folder after its real title
+/// rather than the sanitized folder name Takeout uses. Albums whose titles would clash with
+/// another output folder keep their Takeout folder name.
+fn build_albums(
+    album_metadata: &HashMap<String, String>,
+    media_files: &[&ArchiveFile],
+    photo_path_prefix: &str,
+) -> HashMap<String, Album> {
+    let mut albums = HashMap::new();
+    for (path, json) in album_metadata {
+        let album_path = extract_album_path(path, photo_path_prefix);
+        let metadata = match parse_album_metadata(json) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                println!("  Warning: Could not read album metadata {}: {}", path, e);
                 continue;
             }
-            let entry_path = entry
-                .path()
-                .map_err(|e| ProcessError::ArchiveError(format!("Failed to get path: {}", e)))?;
-            let mut entry_path_str = entry_path.to_string_lossy().to_string();
-            if let Some(stripped) = entry_path_str.strip_prefix("./") {
-                entry_path_str = stripped.to_string();
-            }
+        };
+        let title = metadata.title.trim().to_string();
+        let Some(folder_name) = sanitize_folder_name(&title) else {
+            continue;
+        };
+        let output_path = match album_path.rfind('/') {
+            Some(slash) => format!("{}/{}", &album_path[..slash], folder_name),
+            None => folder_name,
+        };
+        albums.insert(album_path, Album { title, output_path });
+    }
 
-            if wanted_paths.contains(&entry_path_str) {
-                let mut contents = Vec::new();
-                entry
-                    .read_to_end(&mut contents)
-                    .map_err(|e| ProcessError::IoError(format!("Failed to read contents: {}", e)))?;
-                let json_str = String::from_utf8(contents).map_err(|e| {
-                    ProcessError::IoError(format!("Invalid UTF-8 in metadata: {}", e))
-                })?;
-                metadata_map.insert(entry_path_str, json_str);
-            } else {
-                std::io::copy(&mut entry, &mut std::io::sink())
-                    .map_err(|e| ProcessError::IoError(format!("Failed to skip entry: {}", e)))?;
+    // Count how many folders end up at each output path, ignoring case for case-insensitive
+    // file systems. Moving a clashing album back can cause a new clash, so repeat until none.
+    let album_paths: HashSet<String> = media_files
+        .iter()
+        .map(|f| extract_album_path(&f.archive_path, photo_path_prefix))
+        .chain(albums.keys().cloned())
+        .collect();
+    loop {
+        let mut output_paths: HashMap<String, usize> = HashMap::new();
+        for album_path in &album_paths {
+            let output_path = albums.get(album_path).map_or(album_path, |a| &a.output_path);
+            *output_paths.entry(output_path.to_lowercase()).or_default() += 1;
+        }
+
+        let mut changed = false;
+        for (album_path, album) in albums.iter_mut() {
+            if album.output_path != *album_path
+                && output_paths[&album.output_path.to_lowercase()] > 1
+            {
+                album.output_path = album_path.clone();
+                changed = true;
             }
         }
+        if !changed {
+            return albums;
+        }
     }
+}
 
-    Ok(metadata_map)
+/// The size and hash shared by identical media files
+type ContentKey = (u64, ContentHash);
+
+/// Media files with the same contents, which Takeout writes once to the year folder and again
+/// to every album the media is in. One copy is written and the others are linked to it.
+#[derive(Default)]
+struct DuplicateGroup {
+    /// The archive path of the copy the group's media is linked to, unless none is written yet
+    copy: Option<String>,
+    /// Whether the copy lies outside any album, which is preferred over one inside an album
+    copy_outside_album: bool,
+    /// The archive path of every copy written, and whether it lies outside any album
+    written: Vec<(String, bool)>,
 }
-/// Process all files in the takeout and output to the specified directory
-pub fn process_takeout(
-    takeout: &Takeout,
-    output_dir: &Path,
-    photo_path_prefix: &str,
-    dry_run: bool,
-    debug: bool,
-    show_progress: bool,
-) -> Result<ProcessStats, ProcessError> {
-    let mut stats = ProcessStats::default();
-    let mut used_metadata = HashSet::new();
-    let mut archive_cache = ArchiveCache::new();
 
-    let metadata_cache = build_metadata_cache(takeout, &mut archive_cache)?;
+impl DuplicateGroup {
+    /// Returns true if media should be written as the group's copy rather than linked to it
+    fn prefers(&self, outside_album: bool) -> bool {
+        self.copy.is_none() || (outside_album && !self.copy_outside_album)
+    }
+}
 
-    // Collect all media files (non-metadata files)
-    let media_files: Vec<_> = takeout
-        .files()
-        .filter(|f| is_media_file(&f.archive_path))
-        .collect();
+/// A duplicate media file waiting to be linked to the copy written in its place
+struct PendingLink {
+    /// Where the duplicate would have been written
+    output_path: PathBuf,
+    /// The archive path of the duplicate
+    archive_path: String,
+    /// The contents of the duplicate
+    content: ContentKey,
+    /// Whether the duplicate was written before a copy outside any album replaced it, so it
+    /// already owns its output path
+    written: bool,
+}
 
-    println!("\nProcessing {} media files...", media_files.len());
+/// Removes an output file and the XMP sidecar next to it, if any
+fn remove_output(output_path: &Path) {
+    let _ = fs::remove_file(output_path);
+    let _ = fs::remove_file(xmp_sidecar_path(output_path));
+}
 
-    let progress = if show_progress {
-        let pb = ProgressBar::new(media_files.len() as u64);
-        let style = ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}<{eta_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
-        )
-        .unwrap_or_else(|_| ProgressStyle::default_bar());
-        pb.set_style(style);
-        Some(pb)
-    } else {
-        None
-    };
+/// Links `duplicate` to `original`, replacing a smaller file already written there. The link
+/// is made next to it and renamed over it, so `duplicate` is never missing.
+fn replace_with_link(mode: DedupMode, original: &Path, duplicate: &Path) -> std::io::Result<()> {
+    let temp = temp_path(duplicate);
+    let result = link_file(mode, original, &temp).and_then(|()| fs::rename(&temp, duplicate));
+    // Renaming a hardlink over another link to the same file leaves both in place
+    let _ = fs::remove_file(&temp);
+    result
+}
 
-    let zip_media_files: Vec<_> = media_files
-        .iter()
-        .filter(|f| is_zip_archive(&f.source_archive))
-        .collect();
+/// Links every duplicate to the copy of its group written in its place, or lists them all in
+/// the album manifest. Copies written before one outside any album are replaced by links too.
+/// Duplicates of which no copy was written are reported as errors.
+fn link_duplicates(
+    output_dir: &Path,
+    options: &ProcessOptions,
+    mut links: Vec<PendingLink>,
+    groups: HashMap<ContentKey, DuplicateGroup>,
+    written: &HashMap<String, PathBuf>,
+    claims: &OutputClaims,
+    stats: &mut ProcessStats,
+) -> Result<(), ProcessError> {
+    let mut originals: HashMap<ContentKey, String> = HashMap::new();
+    for (content, group) in groups {
+        // A copy can lose its output path to a larger file after it was written
+        let mut copies: Vec<_> = group
+            .written
+            .into_iter()
+            .filter(|(archive_path, _)| {
+                written.get(archive_path).is_some_and(|output_path| {
+                    claims.owner(output_path).as_deref() == Some(archive_path.as_str())
+                })
+            })
+            .collect();
+        copies.sort_by(|a, b| (!a.1, &a.0).cmp(&(!b.1, &b.0)));
+        let Some(((original, _), rest)) = copies.split_first() else {
+            continue;
+        };
+        for (archive_path, _) in rest {
+            links.push(PendingLink {
+                output_path: written[archive_path].clone(),
+                archive_path: archive_path.clone(),
+                content,
+                written: true,
+            });
+        }
+        originals.insert(content, original.clone());
+    }
 
-    for file in zip_media_files {
-        let file_name = file.file_name();
-        let album = extract_album_path(&file.archive_path, photo_path_prefix);
-        let output_path = output_dir.join(&album).join(file_name);
+    let mode = options.dedup;
+    let mut manifest: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for link in links {
+        let Some(original_archive_path) = originals.get(&link.content) else {
+            eprintln!(
+                "  Error linking {}: no identical media was written",
+                link.output_path.display()
+            );
+            stats.errors += 1;
+            continue;
+        };
+        let original = &written[original_archive_path];
 
-        if debug {
-            if let Some(pb) = progress.as_ref() {
-                pb.println(format!("  Processing: {}/{}", album, file_name));
+        // Layouts without the album can put every copy in the same place
+        if claims.owner(&link.output_path).as_ref() == Some(original_archive_path) {
+            stats.duplicates += 1;
+            continue;
+        }
+
+        if mode == DedupMode::Manifest {
+            if link.written {
+                remove_output(&link.output_path);
+            }
+            let relative = |path: &Path| {
+                let path = path.strip_prefix(output_dir).unwrap_or(path);
+                path.to_string_lossy().replace('\\', "/")
+            };
+            let album = link.output_path.parent().map(relative).unwrap_or_default();
+            manifest.entry(album).or_default().push(relative(original));
+            stats.duplicates += 1;
+            continue;
+        }
+
+        let output_path = if link.written {
+            link.output_path
+        } else {
+            let claim = claims.claim(
+                &link.output_path,
+                &link.archive_path,
+                link.content.0,
+                options.on_collision,
+            );
+            if let Some(collision) = claim.collision {
+                if collision.resolution == Resolution::Failed {
+                    eprintln!(
+                        "  Error linking {}: {} is already written from {}",
+                        collision.incoming,
+                        collision.output_path.display(),
+                        collision.existing
+                    );
+                    stats.errors += 1;
+                }
+                stats.collisions.push(collision);
+            }
+            let Some(output_path) = claim.path else {
+                continue;
+            };
+            output_path
+        };
+
+        // Sidecars written next to the copy belong to the duplicate too
+        let original_xmp = xmp_sidecar_path(original);
+        let result = replace_with_link(mode, original, &output_path).and_then(|()| {
+            if original_xmp.exists() {
+                replace_with_link(mode, &original_xmp, &xmp_sidecar_path(&output_path))?;
             } else {
-                println!("  Processing: {}/{}", album, file_name);
+                let _ = fs::remove_file(xmp_sidecar_path(&output_path));
             }
+            Ok(())
+        });
+        match result {
+            Ok(()) => stats.duplicates += 1,
+            Err(e) => {
+                eprintln!("  Error linking {}: {}", output_path.display(), e);
+                stats.errors += 1;
+            }
+        }
+    }
+
+    if mode == DedupMode::Manifest && !manifest.is_empty() {
+        for media in manifest.values_mut() {
+            media.sort();
+        }
+        write_album_manifest(output_dir, &manifest).map_err(|e| {
+            ProcessError::IoError(format!("Failed to write {}: {}", ALBUM_MANIFEST_FILE, e))
+        })?;
+    }
+    Ok(())
+}
+
+/// The sidecar of a media file, parsed once for everything that reads it
+struct Sidecar<'a> {
+    json: &'a str,
+    metadata: GoogleSupplementalMetadata,
+}
+
+/// What is known about a media file before it is read, decided for all media at once
+struct MediaPlan<'a> {
+    /// The JSON of the media file's sidecar, if one was found
+    metadata_json: Option<&'a str>,
+    /// The parsed sidecar. One that cannot be parsed fails the file when it is written.
+    sidecar: Result<Option<Sidecar<'a>>, MetadataError>,
+    /// Where the media file is written, or `None` if it is left out
+    output_path: Option<PathBuf>,
+}
+
+impl MediaPlan<'_> {
+    fn google_meta(&self) -> Option<&GoogleSupplementalMetadata> {
+        self.sidecar.as_ref().ok().and_then(|s| s.as_ref()).map(|s| &s.metadata)
+    }
+}
+
+/// Shared state for processing the media files of a takeout across worker threads
+struct ProcessContext<'a> {
+    takeout: &'a Takeout,
+    metadata_cache: &'a HashMap<String, String>,
+    /// The sidecar lookup of each media file, keyed by media path
+    metadata_lookups: &'a HashMap<String, MetadataLookup<'a>>,
+    /// The media file each sidecar found by title belongs to, keyed by sidecar path
+    title_owners: &'a HashMap<String, String>,
+    /// Albums with a metadata file, keyed by their path below the photo folder
+    albums: &'a HashMap<String, Album>,
+    /// Sizes shared by several media files, which are hashed to find duplicates when
+    /// deduplicating
+    shared_sizes: &'a HashSet<u64>,
+    output_dir: &'a Path,
+    photo_path_prefix: &'a str,
+    options: &'a ProcessOptions,
+    progress: Option<ProgressBar>,
+    stats: Mutex<ProcessStats>,
+    used_metadata: Mutex<HashSet<String>>,
+    /// Identical media files, keyed by their contents
+    duplicate_groups: Mutex<HashMap<ContentKey, DuplicateGroup>>,
+    /// Duplicates to link once all media is written
+    pending_links: Mutex<Vec<PendingLink>>,
+    /// Where media was written, keyed by archive path, when deduplicating
+    written: Mutex<HashMap<String, PathBuf>>,
+    /// The output path of every media file, to detect collisions
+    output_claims: OutputClaims,
+    /// Records the media files written, unless this is a dry run
+    journal: Option<Journal>,
+    /// Media files written by an earlier run, keyed by archive path
+    completed: &'a HashMap<String, JournalEntry>,
+    /// The first error that aborted processing
+    failure: Mutex<Option<ProcessError>>,
+    aborted: AtomicBool,
+}
+
+impl<'a> ProcessContext<'a> {
+    /// Prints a line without garbling the progress bar
+    fn println(&self, msg: String) {
+        if let Some(pb) = self.progress.as_ref() {
+            pb.println(msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    /// Prints an error line without garbling the progress bar
+    fn eprintln(&self, msg: String) {
+        if let Some(pb) = self.progress.as_ref() {
+            pb.println(msg);
+        } else {
+            eprintln!("{}", msg);
+        }
+    }
+
+    fn inc_progress(&self) {
+        if let Some(pb) = self.progress.as_ref() {
+            pb.inc(1);
         }
+    }
 
-        // Find associated metadata
-        let metadata_file = takeout.find_metadata_for(&file.archive_path);
-        if let Some(meta_file) = metadata_file {
-            used_metadata.insert(meta_file.archive_path.clone());
+    /// Records an error that stops all workers. Only the first error is kept.
+    fn abort(&self, error: ProcessError) {
+        let mut failure = lock(&self.failure);
+        if failure.is_none() {
+            *failure = Some(error);
         }
+        self.aborted.store(true, Ordering::SeqCst);
+    }
 
-        let metadata_json = metadata_file
-            .and_then(|meta| metadata_cache.get(&meta.archive_path))
-            .map(|s| s.as_str());
+    fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
 
-        if dry_run {
-            if metadata_file.is_some() {
-                if let Some(pb) = progress.as_ref() {
-                    pb.println(format!(
-                        "  [DRY RUN] Would process: {} -> {}",
-                        file.archive_path,
-                        output_path.display()
+    /// Returns the sidecar looked up for a media file. Sidecars found by title that belong to
+    /// another media file, and ambiguous title matches, are reported and leave the media
+    /// without metadata.
+    fn find_metadata(&self, archive_path: &str) -> Option<MetadataMatch<'a>> {
+        match self.metadata_lookups.get(archive_path)? {
+            MetadataLookup::Found(found) if found.kind != MatchKind::Title => Some(*found),
+            MetadataLookup::Found(found) => {
+                let owner = self.title_owners.get(&found.file.archive_path);
+                if let Some(owner) = owner.filter(|owner| *owner != archive_path) {
+                    self.eprintln(format!(
+                        "  Warning: the sidecar with the title of {} belongs to {}, not applying it:\n    {}",
+                        archive_path, owner, found.file.archive_path
                     ));
-                } else {
-                    println!(
-                        "  [DRY RUN] Would process: {} -> {}",
-                        file.archive_path,
-                        output_path.display()
-                    );
+                    lock(&self.stats).title_ambiguous += 1;
+                    return None;
                 }
-                stats.metadata_applied += 1;
-                if is_image_file(&file.archive_path) {
-                    stats.images_processed_with_metadata += 1;
+                lock(&self.stats).title_matched += 1;
+                Some(*found)
+            }
+            MetadataLookup::Ambiguous(candidates) => {
+                let mut candidates = candidates.clone();
+                candidates.sort_by(|a, b| a.archive_path.cmp(&b.archive_path));
+                let mut msg = format!(
+                    "  Warning: {} sidecars have the title of {}, not applying any:",
+                    candidates.len(),
+                    archive_path
+                );
+                for candidate in candidates {
+                    msg.push_str(&format!("\n    {}", candidate.archive_path));
                 }
-            } else {
-                if let Some(pb) = progress.as_ref() {
-                    pb.println(format!(
-                        "  [DRY RUN] Would copy (no metadata): {} -> {}",
-                        file.archive_path,
-                        output_path.display()
-                    ));
-                } else {
-                    println!(
-                        "  [DRY RUN] Would copy (no metadata): {} -> {}",
-                        file.archive_path,
-                        output_path.display()
-                    );
+                self.eprintln(msg);
+                lock(&self.stats).title_ambiguous += 1;
+                None
+            }
+            MetadataLookup::NotFound => None,
+        }
+    }
+
+    /// Decides the sidecar and output path of every media file before any is written. Files
+    /// are planned in order of their archive path, so collisions between output paths are
+    /// resolved the same way in every run.
+    fn plan_media(
+        &self,
+        media_files: &[&'a ArchiveFile],
+    ) -> Result<HashMap<String, MediaPlan<'a>>, ProcessError> {
+        let mut media_files = media_files.to_vec();
+        media_files.sort_by(|a, b| a.archive_path.cmp(&b.archive_path));
+
+        let mut plans: Vec<_> = media_files
+            .into_iter()
+            .map(|file| {
+                let metadata_match = self.find_metadata(&file.archive_path);
+                if let Some(found) = metadata_match {
+                    lock(&self.used_metadata).insert(found.file.archive_path.clone());
                 }
-                stats.media_copied_without_metadata += 1;
-                if is_image_file(&file.archive_path) {
-                    stats.images_processed_without_metadata += 1;
-                } else {
-                    stats.videos_copied += 1;
+                match metadata_match.map(|m| m.kind) {
+                    Some(MatchKind::Edited) => lock(&self.stats).edited_matched += 1,
+                    Some(MatchKind::Companion) => lock(&self.stats).companions_matched += 1,
+                    _ => {}
                 }
+
+                let metadata_json = metadata_match
+                    .and_then(|found| self.metadata_cache.get(&found.file.archive_path))
+                    .map(|s| s.as_str());
+                let sidecar = metadata_json
+                    .map(|json| {
+                        parse_google_metadata(json).map(|metadata| Sidecar { json, metadata })
+                    })
+                    .transpose();
+                let plan = MediaPlan {
+                    metadata_json,
+                    sidecar,
+                    output_path: None,
+                };
+                (file, plan)
+            })
+            .collect();
+
+        // Dated layouts use the capture time from the sidecar, then from the EXIF of images,
+        // then the time the file was last modified in the archive
+        let mut dates = vec![None; plans.len()];
+        if self.options.layout.uses_date() {
+            for ((_, plan), date) in plans.iter().zip(&mut dates) {
+                *date = plan.google_meta().and_then(|meta| self.sidecar_date(meta));
             }
-            stats.images_processed += 1;
-            if let Some(pb) = progress.as_ref() {
-                pb.inc(1);
+            let undated_images: Vec<_> = plans
+                .iter()
+                .zip(&dates)
+                .filter(|((file, _), date)| date.is_none() && is_image_file(&file.archive_path))
+                .map(|((file, _), _)| *file)
+                .collect();
+            let exif_dates = read_exif_dates(self.takeout, &undated_images)?;
+            for ((file, _), date) in plans.iter().zip(&mut dates) {
+                if date.is_none() {
+                    *date = exif_dates
+                        .get(&file.archive_path)
+                        .copied()
+                        .or_else(|| self.archive_date(&file.archive_path));
+                }
             }
-            continue;
         }
 
-        // Process based on file type
-        let result = if is_image_file(&file.archive_path) {
-            let image_data = read_zip_file_cached(&mut archive_cache, file)?;
-            process_image_data(&file.archive_path, image_data, metadata_json, &output_path, debug)
-        } else {
-            // Video or other file - just copy
-            let data = read_zip_file_cached(&mut archive_cache, file)?;
-            copy_file_data(data, &output_path, debug).map(|_| false)
-        };
+        // Several media files can map to the same output path, e.g. with a flat layout
+        for ((file, plan), date) in plans.iter_mut().zip(dates) {
+            let Some(output_path) =
+                self.layout_output_path(&file.archive_path, plan.google_meta(), date)
+            else {
+                continue;
+            };
+            let claim = self.output_claims.claim(
+                &output_path,
+                &file.archive_path,
+                file.size,
+                self.options.on_collision,
+            );
+            if let Some(collision) = claim.collision {
+                self.record_collision(collision);
+            }
+            plan.output_path = claim.path;
+        }
 
-        match result {
-            Ok(had_metadata) => {
-                stats.images_processed += 1;
-                if had_metadata {
-                    stats.metadata_applied += 1;
-                    stats.images_processed_with_metadata += 1;
-                } else {
-                    stats.media_copied_without_metadata += 1;
-                    stats.images_processed_without_metadata += 1;
+        // A larger file can take over the path of one planned before it
+        Ok(plans
+            .into_iter()
+            .map(|(file, mut plan)| {
+                plan.output_path = plan.output_path.filter(|path| {
+                    self.output_claims.owner(path).as_deref() == Some(&file.archive_path)
+                });
+                (file.archive_path.clone(), plan)
+            })
+            .collect())
+    }
+
+    /// Returns where the layout puts a media file, or `None` if it is trashed media that is
+    /// left out
+    fn layout_output_path(
+        &self,
+        archive_path: &str,
+        google_meta: Option<&GoogleSupplementalMetadata>,
+        date: Option<NaiveDate>,
+    ) -> Option<PathBuf> {
+        let file_name = Path::new(archive_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("");
+        let album_path = extract_album_path(archive_path, self.photo_path_prefix);
+        let album = self.albums.get(&album_path).map_or(album_path, |a| a.output_path.clone());
+        let relative_path = self.options.layout.render(&LayoutFields {
+            album: &album,
+            filename: file_name,
+            date,
+        });
+        let output_path = self.output_dir.join(&relative_path);
+
+        // Media in the trash or archive of Google Photos can be left out or kept apart
+        match google_meta {
+            Some(meta) if meta.trashed == Some(true) => {
+                lock(&self.stats).trashed += 1;
+                match self.options.trashed {
+                    TrashedMode::Skip => {
+                        if self.options.debug {
+                            self.println(format!("  Skipping trashed: {}", archive_path));
+                        }
+                        None
+                    }
+                    TrashedMode::Include => Some(output_path),
+                    TrashedMode::Separate => {
+                        Some(self.output_dir.join(TRASHED_DIR).join(&relative_path))
+                    }
                 }
             }
-            Err(e) => {
-                if let Some(pb) = progress.as_ref() {
-                    pb.println(format!("  Error processing {}: {}", file.archive_path, e));
-                } else {
-                    eprintln!("  Error processing {}: {}", file.archive_path, e);
+            Some(meta) if meta.archived == Some(true) => {
+                lock(&self.stats).archived += 1;
+                match self.options.archived {
+                    ArchivedMode::Include => Some(output_path),
+                    ArchivedMode::Separate => {
+                        Some(self.output_dir.join(ARCHIVED_DIR).join(&relative_path))
+                    }
                 }
-                stats.errors += 1;
             }
+            _ => Some(output_path),
         }
+    }
 
-        if let Some(pb) = progress.as_ref() {
-            pb.inc(1);
-        }
+    /// Returns the local date media was taken according to its sidecar
+    fn sidecar_date(&self, google_meta: &GoogleSupplementalMetadata) -> Option<NaiveDate> {
+        let timestamp = google_meta.taken_timestamp().ok().flatten()?;
+        local_date(timestamp, google_meta.utc_offset_at(timestamp, self.options.metadata.timezone))
     }
 
-    let tar_archives: Vec<_> = takeout
-        .source_archives()
-        .iter()
-        .filter(|p| is_tar_gz_archive(p))
-        .cloned()
-        .collect();
+    /// Returns the local date a file was last modified in its archive
+    fn archive_date(&self, archive_path: &str) -> Option<NaiveDate> {
+        let timestamp = self.takeout.get(archive_path)?.modified?;
+        let offset = self.options.metadata.timezone.map_or(0, |tz| tz.offset_at(timestamp));
+        local_date(timestamp, offset)
+    }
 
-    for archive_path in tar_archives {
-        let file = File::open(&archive_path)
-            .map_err(|e| ProcessError::IoError(format!("Failed to open archive: {}", e)))?;
-        let reader = BufReader::new(file);
-        let decoder = GzDecoder::new(reader);
-        let mut archive = TarArchive::new(decoder);
-
-        let entries = archive
-            .entries()
-            .map_err(|e| ProcessError::ArchiveError(format!("Failed to read tar entries: {}", e)))?;
-
-        for entry in entries {
-            let mut entry = entry
-                .map_err(|e| ProcessError::ArchiveError(format!("Failed to read entry: {}", e)))?;
-            if !entry.header().entry_type().is_file() {
-                continue;
-            }
+    /// Records a media file as completely written in the journal, along with the hash of the
+    /// data written
+    fn record_in_journal(
+        &self,
+        archive_path: &str,
+        output_path: &Path,
+        hash: String,
+        metadata_json: Option<&str>,
+    ) -> Result<(), ProcessError> {
+        let (Some(journal), Some(file)) = (&self.journal, self.takeout.get(archive_path)) else {
+            return Ok(());
+        };
+        let journal_error = |e| ProcessError::IoError(format!("Failed to update journal: {}", e));
+        let entry = JournalEntry {
+            path: archive_path.to_string(),
+            source: file.source_archive.clone(),
+            size: file.size,
+            output: output_path.strip_prefix(self.output_dir).unwrap_or(output_path).to_path_buf(),
+            hash,
+            metadata_hash: metadata_json.map(hash_metadata),
+        };
+        journal.record(&entry).map_err(journal_error)
+    }
+
+    /// Hashes media sharing its size with another media file, returning its key among
+    /// duplicates. Other media cannot have duplicates and is not read.
+    fn content_key(
+        &self,
+        size: u64,
+        data: &mut Option<Vec<u8>>,
+        read_data: &mut impl FnMut() -> Result<Vec<u8>, ProcessError>,
+    ) -> Result<Option<ContentKey>, ProcessError> {
+        if !self.shared_sizes.contains(&size) {
+            return Ok(None);
+        }
+        let bytes = match data.take() {
+            Some(bytes) => bytes,
+            None => read_data()?,
+        };
+        let hash = hash_contents(&mut bytes.as_slice())
+            .map_err(|e| ProcessError::IoError(format!("Failed to hash file: {}", e)))?;
+        *data = Some(bytes);
+        Ok(Some((size, hash)))
+    }
 
-            let entry_path = entry
-                .path()
-                .map_err(|e| ProcessError::ArchiveError(format!("Failed to get path: {}", e)))?;
-            let mut entry_path_str = entry_path.to_string_lossy().to_string();
-            if let Some(stripped) = entry_path_str.strip_prefix("./") {
-                entry_path_str = stripped.to_string();
+    /// Records whether a copy of a duplicate group was written. If the group's copy was not,
+    /// the next duplicate is written in its place.
+    fn record_copy(
+        &self,
+        content: ContentKey,
+        archive_path: &str,
+        outside_album: bool,
+        written: bool,
+    ) {
+        let mut groups = lock(&self.duplicate_groups);
+        let group = groups.entry(content).or_default();
+        if written {
+            if group.prefers(outside_album) {
+                group.copy = Some(archive_path.to_string());
+                group.copy_outside_album = outside_album;
             }
+            group.written.push((archive_path.to_string(), outside_album));
+        } else if group.copy.as_deref() == Some(archive_path) {
+            group.copy = None;
+        }
+    }
 
-            if !entry_path_str.starts_with(photo_path_prefix) || !is_media_file(&entry_path_str) {
-                std::io::copy(&mut entry, &mut std::io::sink())
-                    .map_err(|e| ProcessError::IoError(format!("Failed to skip entry: {}", e)))?;
-                continue;
+    /// Adds a collision to the stats, reporting it if it fails the media file
+    fn record_collision(&self, collision: Collision) {
+        if collision.resolution == Resolution::Failed {
+            self.eprintln(format!(
+                "  Error processing {}: {} is already written from {}",
+                collision.incoming,
+                collision.output_path.display(),
+                collision.existing
+            ));
+            lock(&self.stats).errors += 1;
+        }
+        lock(&self.stats).collisions.push(collision);
+    }
+
+    /// Counts the fields of a sidecar this tool does not know about. In strict mode these
+    /// fail the media file instead.
+    fn check_unknown_fields(&self, sidecar: &Sidecar) -> Result<(), ProcessError> {
+        let unknown_fields = sidecar.metadata.unknown_fields();
+        if !unknown_fields.is_empty() {
+            let mut stats = lock(&self.stats);
+            for field in unknown_fields {
+                *stats.unknown_fields.entry(field).or_default() += 1;
             }
+        }
 
-            let file_name = entry_path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("");
-            let album = extract_album_path(&entry_path_str, photo_path_prefix);
-            let output_path = output_dir.join(&album).join(file_name);
+        if self.options.strict {
+            sidecar.metadata.reject_unknown_fields(sidecar.json)?;
+        }
+        Ok(())
+    }
 
-            if debug {
-                if let Some(pb) = progress.as_ref() {
-                    pb.println(format!("  Processing: {}/{}", album, file_name));
+    /// Writes a media file with its metadata to `output_path`, returning whether it had metadata
+    /// and the hash of the data written
+    fn write_media(
+        &self,
+        archive_path: &str,
+        data: Vec<u8>,
+        sidecar: Option<&Sidecar>,
+        output_path: &Path,
+        keywords: &[String],
+    ) -> Result<(bool, String), ProcessError> {
+        if let Some(sidecar) = sidecar {
+            self.check_unknown_fields(sidecar)?;
+        }
+        let google_meta = sidecar.map(|sidecar| &sidecar.metadata);
+        let options = &self.options.metadata;
+        let debug = self.options.debug;
+
+        let (data, xmp_sidecar, had_metadata) = if self.options.metadata_mode == MetadataMode::Xmp
+        {
+            let xmp = full_xmp_sidecar(google_meta, keywords, options, debug)?;
+            (data, xmp, google_meta.is_some())
+        } else {
+            let (data, had_metadata) = if is_image_file(archive_path) {
+                let (data, counts) =
+                    process_image_data(archive_path, data, google_meta, options, debug)?;
+                if let Some(counts) = &counts {
+                    let mut stats = lock(&self.stats);
+                    stats.fields_kept += counts.kept;
+                    stats.fields_overwritten += counts.overwritten;
+                }
+                (data, counts.is_some())
+            } else {
+                process_video_data(archive_path, data, google_meta, debug)?
+            };
+
+            // EXIF has no tags for e.g. people, so those are written as XMP alongside it
+            let (data, xmp) =
+                add_xmp_extras(output_path, data, google_meta, keywords, options, debug)?;
+            (data, xmp, had_metadata)
+        };
+
+        let modified = match google_meta {
+            Some(google_meta) if self.options.set_file_times => file_time(google_meta)?,
+            _ => None,
+        };
+        write_output(output_path, &data, xmp_sidecar.as_deref(), modified)?;
+        Ok((had_metadata, hash_data(&data)))
+    }
+
+    /// Processes a single media entry as planned. `read` is only called when the contents are
+    /// needed. Failures to process the file are counted in the stats, failures to read it are
+    /// returned.
+    fn process_entry(
+        &self,
+        archive_path: &str,
+        plan: &MediaPlan,
+        read: impl FnOnce() -> Result<Vec<u8>, ProcessError>,
+    ) -> Result<(), ProcessError> {
+        let file_name = Path::new(archive_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("");
+        let album_path = extract_album_path(archive_path, self.photo_path_prefix);
+        let album_info = self.albums.get(&album_path);
+        let outside_album = album_info.is_none();
+        let album = album_info.map_or(album_path, |a| a.output_path.clone());
+        let keywords = match album_info {
+            Some(info) if self.options.album_keywords => vec![info.title.clone()],
+            _ => Vec::new(),
+        };
+        let is_image = is_image_file(archive_path);
+
+        if self.options.debug {
+            self.println(format!("  Processing: {}/{}", album, file_name));
+        }
+
+        // Trashed media left out, or media another file took the output path of
+        let Some(output_path) = plan.output_path.clone() else {
+            return Ok(());
+        };
+        let metadata_json = plan.metadata_json;
+
+        // Only read the contents once, whether for hashing or processing
+        let mut read = Some(read);
+        let mut read_data = || match read.take() {
+            Some(read) => read(),
+            None => Err(ProcessError::IoError(format!("{} was read twice", archive_path))),
+        };
+        let mut data = None;
+        let size = self.takeout.get(archive_path).map_or(0, |f| f.size);
+
+        // Media written by an earlier run is left as it is if it has the same size and its
+        // output is still intact, and when updating, if its sidecar has not changed since either
+        let previous = self.completed.get(archive_path);
+        if let Some(entry) = previous {
+            let unchanged = entry.size == size
+                && (!self.options.update
+                    || entry.metadata_hash == metadata_json.map(hash_metadata))
+                && entry.is_intact(self.output_dir);
+            if unchanged {
+                // Its duplicates are still linked to it
+                if let Some(content) = self.content_key(size, &mut data, &mut read_data)? {
+                    let output_path = self.output_dir.join(&entry.output);
+                    lock(&self.written).insert(archive_path.to_string(), output_path);
+                    self.record_copy(content, archive_path, outside_album, true);
+                }
+                let mut stats = lock(&self.stats);
+                if self.options.update {
+                    stats.unchanged += 1;
                 } else {
-                    println!("  Processing: {}/{}", album, file_name);
+                    stats.resumed += 1;
                 }
+                return Ok(());
             }
+        }
 
-            let metadata_file = takeout.find_metadata_for(&entry_path_str);
-            if let Some(meta_file) = metadata_file {
-                used_metadata.insert(meta_file.archive_path.clone());
+        // Identical media in other folders is written once and linked to afterwards,
+        // preferably from outside any album
+        let content = self.content_key(size, &mut data, &mut read_data)?;
+        // Deciding and claiming under one lock keeps two copies of a group from being written
+        let mut groups = lock(&self.duplicate_groups);
+        if let Some(content) = content {
+            let group = groups.entry(content).or_default();
+            if let Some(original) = group.copy.clone()
+                && !group.prefers(outside_album)
+            {
+                drop(groups);
+                if self.options.dry_run {
+                    self.println(format!(
+                        "  [DRY RUN] Would link: {} -> {}",
+                        output_path.display(),
+                        original
+                    ));
+                    lock(&self.stats).duplicates += 1;
+                } else {
+                    lock(&self.pending_links).push(PendingLink {
+                        output_path,
+                        archive_path: archive_path.to_string(),
+                        content,
+                        written: false,
+                    });
+                }
+                return Ok(());
             }
+            group.copy = Some(archive_path.to_string());
+            group.copy_outside_album = outside_album;
+        }
+        drop(groups);
+
+        if self.options.dry_run {
+            let mut stats = lock(&self.stats);
+            if metadata_json.is_some() {
+                self.println(format!(
+                    "  [DRY RUN] Would process: {} -> {}",
+                    archive_path,
+                    output_path.display()
+                ));
+                stats.metadata_applied += 1;
+                if is_image {
+                    stats.images_processed_with_metadata += 1;
+                } else {
+                    stats.videos_copied += 1;
+                }
+            } else {
+                self.println(format!(
+                    "  [DRY RUN] Would copy (no metadata): {} -> {}",
+                    archive_path,
+                    output_path.display()
+                ));
+                stats.media_copied_without_metadata += 1;
+                if is_image {
+                    stats.images_processed_without_metadata += 1;
+                } else {
+                    stats.videos_copied += 1;
+                }
+            }
+            stats.images_processed += 1;
+            return Ok(());
+        }
+
+        // Process based on file type
+        let data = match data {
+            Some(data) => data,
+            None => read_data()?,
+        };
+        let result = match &plan.sidecar {
+            Ok(sidecar) => {
+                self.write_media(archive_path, data, sidecar.as_ref(), &output_path, &keywords)
+            }
+            Err(e) => Err(ProcessError::MetadataError(e.clone())),
+        };
+        if let Some(content) = content {
+            self.record_copy(content, archive_path, outside_album, result.is_ok());
+        }
 
-            let metadata_json = metadata_file
-                .and_then(|meta| metadata_cache.get(&meta.archive_path))
-                .map(|s| s.as_str());
-
-            if dry_run {
-                if metadata_file.is_some() {
-                    if let Some(pb) = progress.as_ref() {
-                        pb.println(format!(
-                            "  [DRY RUN] Would process: {} -> {}",
-                            entry_path_str,
-                            output_path.display()
-                        ));
+        // Only complete files are recorded, so an interrupted run writes the rest again
+        if let Ok((_, hash)) = result.as_ref() {
+            self.record_in_journal(archive_path, &output_path, hash.clone(), metadata_json)?;
+
+            // Changed metadata can move media to another folder, e.g. with a dated layout,
+            // unless its old path is planned for another file
+            if let Some(entry) = previous {
+                let previous_path = self.output_dir.join(&entry.output);
+                let owner = self.output_claims.owner(&previous_path);
+                if previous_path != output_path
+                    && owner.is_none_or(|owner| owner == archive_path)
+                {
+                    remove_output(&previous_path);
+                }
+            }
+        }
+
+        match result {
+            Ok((had_metadata, _)) => {
+                if self.options.dedup != DedupMode::Off {
+                    lock(&self.written).insert(archive_path.to_string(), output_path);
+                }
+                let mut stats = lock(&self.stats);
+                stats.images_processed += 1;
+                if self.options.update {
+                    if previous.is_some() {
+                        stats.changed += 1;
                     } else {
-                        println!(
-                            "  [DRY RUN] Would process: {} -> {}",
-                            entry_path_str,
-                            output_path.display()
-                        );
+                        stats.added += 1;
                     }
+                }
+                if had_metadata {
                     stats.metadata_applied += 1;
-                    if is_image_file(&entry_path_str) {
+                    if is_image {
                         stats.images_processed_with_metadata += 1;
-                    }
-                } else {
-                    if let Some(pb) = progress.as_ref() {
-                        pb.println(format!(
-                            "  [DRY RUN] Would copy (no metadata): {} -> {}",
-                            entry_path_str,
-                            output_path.display()
-                        ));
                     } else {
-                        println!(
-                            "  [DRY RUN] Would copy (no metadata): {} -> {}",
-                            entry_path_str,
-                            output_path.display()
-                        );
+                        stats.videos_copied += 1;
                     }
+                } else {
                     stats.media_copied_without_metadata += 1;
-                    if is_image_file(&entry_path_str) {
+                    if is_image {
                         stats.images_processed_without_metadata += 1;
                     } else {
                         stats.videos_copied += 1;
                     }
                 }
-                stats.images_processed += 1;
-                std::io::copy(&mut entry, &mut std::io::sink())
-                    .map_err(|e| ProcessError::IoError(format!("Failed to skip entry: {}", e)))?;
-                if let Some(pb) = progress.as_ref() {
-                    pb.inc(1);
-                }
-                continue;
             }
+            Err(e) => {
+                self.eprintln(format!("  Error processing {}: {}", archive_path, e));
+                lock(&self.stats).errors += 1;
+            }
+        }
 
-            let result = if is_image_file(&entry_path_str) {
-                let mut image_data = Vec::new();
-                entry
-                    .read_to_end(&mut image_data)
-                    .map_err(|e| ProcessError::IoError(format!("Failed to read contents: {}", e)))?;
-                process_image_data(&entry_path_str, image_data, metadata_json, &output_path, debug)
-            } else {
-                let mut data = Vec::new();
-                entry
-                    .read_to_end(&mut data)
-                    .map_err(|e| ProcessError::IoError(format!("Failed to read contents: {}", e)))?;
-                copy_file_data(data, &output_path, debug).map(|_| false)
-            };
+        Ok(())
+    }
+}
 
-            match result {
-                Ok(had_metadata) => {
-                    stats.images_processed += 1;
-                    if had_metadata {
-                        stats.metadata_applied += 1;
-                        if is_image_file(&entry_path_str) {
-                            stats.images_processed_with_metadata += 1;
-                        }
-                    } else {
-                        stats.media_copied_without_metadata += 1;
-                        if is_image_file(&entry_path_str) {
-                            stats.images_processed_without_metadata += 1;
-                        } else {
-                            stats.videos_copied += 1;
+/// A media entry streamed from a sequential source, with its contents
+type StreamedEntry = (String, Vec<u8>);
+
+/// Processes all media in one set of workers. Sequential sources are each streamed front to
+/// back on a thread of their own, handing their entries to the workers over a bounded channel
+/// so at most a few are held in memory at once. Workers take streamed entries first, so the
+/// readers keep going, and otherwise pull the next file of the random-access sources from a
+/// shared counter, keeping their own reader for each source.
+fn process_media(
+    ctx: &ProcessContext,
+    plans: &HashMap<String, MediaPlan>,
+    files: &[&ArchiveFile],
+    sequential_sources: &[&dyn ArchiveSource],
+) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::sync_channel::<StreamedEntry>(ctx.options.jobs * 2);
+    let receiver = Mutex::new(receiver);
+
+    thread::scope(|scope| {
+        for &source in sequential_sources {
+            let sender = sender.clone();
+            scope.spawn(move || {
+                // A worker failure takes precedence over the resulting "aborted" read error
+                if let Err(e) = stream_source(ctx, plans, source, &sender)
+                    && !ctx.is_aborted()
+                {
+                    ctx.abort(e);
+                }
+            });
+        }
+        drop(sender);
+
+        for _ in 0..ctx.options.jobs {
+            scope.spawn(|| {
+                let mut cache = ArchiveCache::new();
+                loop {
+                    // Keep draining after an abort so no reader blocks on a full channel
+                    if ctx.is_aborted() {
+                        match lock(&receiver).recv() {
+                            Ok(_) => continue,
+                            Err(_) => break,
                         }
                     }
-                }
-                Err(e) => {
-                    if let Some(pb) = progress.as_ref() {
-                        pb.println(format!("  Error processing {}: {}", entry_path_str, e));
-                    } else {
-                        eprintln!("  Error processing {}: {}", entry_path_str, e);
+
+                    let streamed = lock(&receiver).try_recv();
+                    let result = match streamed {
+                        Ok((archive_path, data)) => {
+                            ctx.process_entry(&archive_path, &plans[&archive_path], || Ok(data))
+                        }
+                        Err(e) => match files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            Some(file) => {
+                                let plan = &plans[&file.archive_path];
+                                let read = || read_file_cached(ctx.takeout, &mut cache, file);
+                                ctx.process_entry(&file.archive_path, plan, read)
+                            }
+                            None if e == TryRecvError::Disconnected => break,
+                            // Only streamed entries are left, so wait for the next one
+                            None => match lock(&receiver).recv() {
+                                Ok((archive_path, data)) => {
+                                    let plan = &plans[&archive_path];
+                                    ctx.process_entry(&archive_path, plan, || Ok(data))
+                                }
+                                Err(_) => break,
+                            },
+                        },
+                    };
+                    if let Err(e) = result {
+                        ctx.abort(e);
+                        continue;
                     }
-                    stats.errors += 1;
+                    ctx.inc_progress();
                 }
-            }
+            });
+        }
+    });
+}
+
+/// Streams the media entries of a sequential source to the workers
+fn stream_source(
+    ctx: &ProcessContext,
+    plans: &HashMap<String, MediaPlan>,
+    source: &dyn ArchiveSource,
+    sender: &SyncSender<StreamedEntry>,
+) -> Result<(), ProcessError> {
+    source.for_each_entry(&mut |entry, reader| {
+        if ctx.is_aborted() {
+            return Err(std::io::Error::other("processing aborted"));
+        }
+
+        let is_planned_media = plans.contains_key(&entry.path)
+            && ctx.takeout.get(&entry.path).is_some_and(|f| f.source_archive == source.path());
+        if !is_planned_media {
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        // Media that may have duplicates is hashed even in a dry run
+        if !ctx.options.dry_run || ctx.shared_sizes.contains(&entry.size) {
+            reader.read_to_end(&mut data)?;
+        }
+        sender
+            .send((entry.path.clone(), data))
+            .map_err(|_| std::io::Error::other("workers stopped"))
+    })?;
+    Ok(())
+}
+
+/// Process all files in the takeout and output to the specified directory
+pub fn process_takeout(
+    takeout: &Takeout,
+    output_dir: &Path,
+    photo_path_prefix: &str,
+    options: &ProcessOptions,
+) -> Result<ProcessStats, ProcessError> {
+    options.validate()?;
+    let mut archive_cache = ArchiveCache::new();
+
+    let (album_metadata, metadata_cache): (HashMap<_, _>, HashMap<_, _>) =
+        build_metadata_cache(takeout, &mut archive_cache)?
+            .into_iter()
+            .partition(|(path, _)| is_album_metadata_path(path));
+    drop(archive_cache);
+
+    let metadata_titles: SidecarTitles = metadata_cache
+        .iter()
+        .filter_map(|(path, json)| parse_title(json).map(|title| (path.clone(), title)))
+        .collect();
 
-            if let Some(pb) = progress.as_ref() {
-                pb.inc(1);
+    // Collect all media files (non-metadata files)
+    let media_files: Vec<_> = takeout
+        .files()
+        .filter(|f| is_media_file(&f.archive_path))
+        .collect();
+
+    let metadata_lookups = takeout.look_up_metadata(
+        media_files.iter().map(|f| f.archive_path.as_str()),
+        &metadata_titles,
+    );
+    let title_owners = claim_title_metadata(&metadata_lookups, &metadata_titles);
+
+    let albums = build_albums(&album_metadata, &media_files, photo_path_prefix);
+
+    // Only media sharing its size with another can have duplicates, so only that is hashed
+    let mut shared_sizes = HashSet::new();
+    if options.dedup != DedupMode::Off {
+        let mut sizes = HashSet::new();
+        for file in media_files.iter().filter(|f| f.size > 0) {
+            if !sizes.insert(file.size) {
+                shared_sizes.insert(file.size);
             }
         }
     }
 
-    if let Some(pb) = progress.as_ref() {
+    println!(
+        "\nProcessing {} media files using {} worker(s)...",
+        media_files.len(),
+        options.jobs
+    );
+
+    let progress = if options.show_progress {
+        let pb = ProgressBar::new(media_files.len() as u64);
+        let style = ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}<{eta_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
+        )
+        .unwrap_or_else(|_| ProgressStyle::default_bar());
+        pb.set_style(style);
+        Some(pb)
+    } else {
+        None
+    };
+
+    // Media written by an earlier run keeps its output path, so nothing else may claim it
+    let completed = if options.resume || options.update {
+        read_journal(output_dir)
+            .map_err(|e| ProcessError::IoError(format!("Failed to read journal: {}", e)))?
+    } else {
+        HashMap::new()
+    };
+    if (options.resume || options.update) && !options.dry_run && output_dir.exists() {
+        let removed = remove_temp_files(output_dir).map_err(|e| {
+            ProcessError::IoError(format!("Failed to remove temporary files: {}", e))
+        })?;
+        if removed > 0 {
+            println!("Removed {} temporary files left by an earlier run", removed);
+        }
+    }
+    let journal = if options.dry_run {
+        None
+    } else {
+        let journal = Journal::open(output_dir)
+            .map_err(|e| ProcessError::IoError(format!("Failed to open journal: {}", e)))?;
+        Some(journal)
+    };
+
+    let ctx = ProcessContext {
+        takeout,
+        metadata_cache: &metadata_cache,
+        metadata_lookups: &metadata_lookups,
+        title_owners: &title_owners,
+        albums: &albums,
+        shared_sizes: &shared_sizes,
+        output_dir,
+        photo_path_prefix,
+        options,
+        progress,
+        stats: Mutex::new(ProcessStats::default()),
+        used_metadata: Mutex::new(HashSet::new()),
+        duplicate_groups: Mutex::new(HashMap::new()),
+        pending_links: Mutex::new(Vec::new()),
+        written: Mutex::new(HashMap::new()),
+        output_claims: OutputClaims::new(),
+        journal,
+        completed: &completed,
+        failure: Mutex::new(None),
+        aborted: AtomicBool::new(false),
+    };
+
+    let mut completed_entries: Vec<_> = completed.values().collect();
+    completed_entries.sort_by(|a, b| a.path.cmp(&b.path));
+    for entry in completed_entries {
+        let output_path = output_dir.join(&entry.output);
+        ctx.output_claims.claim(&output_path, &entry.path, entry.size, options.on_collision);
+    }
+    let plans = ctx.plan_media(&media_files)?;
+
+    // Files in random-access sources can be read in any order, but reading them
+    // in storage order keeps access to each archive mostly sequential
+    let mut random_access_files: Vec<_> = media_files
+        .iter()
+        .copied()
+        .filter(|f| is_random_access(takeout, f))
+        .collect();
+    random_access_files.sort_by(|a, b| {
+        (&a.source_archive, a.index).cmp(&(&b.source_archive, b.index))
+    });
+
+    // Everything else has to be streamed front to back
+    let sequential_sources: Vec<_> =
+        takeout.sources().filter(|s| !s.supports_random_access()).collect();
+
+    process_media(&ctx, &plans, &random_access_files, &sequential_sources);
+
+    if let Some(pb) = ctx.progress.as_ref() {
         pb.finish_and_clear();
     }
 
+    if let Some(e) = ctx.failure.into_inner().unwrap_or_else(|e| e.into_inner()) {
+        return Err(e);
+    }
+    if let Some(journal) = ctx.journal.as_ref() {
+        journal
+            .sync()
+            .map_err(|e| ProcessError::IoError(format!("Failed to update journal: {}", e)))?;
+    }
+
+    let mut stats = ctx.stats.into_inner().unwrap_or_else(|e| e.into_inner());
+    let used_metadata = ctx
+        .used_metadata
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner());
+
+    let pending_links = ctx.pending_links.into_inner().unwrap_or_else(|e| e.into_inner());
+    let groups = ctx.duplicate_groups.into_inner().unwrap_or_else(|e| e.into_inner());
+    let written = ctx.written.into_inner().unwrap_or_else(|e| e.into_inner());
+    link_duplicates(
+        output_dir,
+        options,
+        pending_links,
+        groups,
+        &written,
+        &ctx.output_claims,
+        &mut stats,
+    )?;
+    stats.collisions.sort_by(|a, b| a.output_path.cmp(&b.output_path));
+
     let unused_metadata: Vec<_> = takeout
         .supplemental_metadata_files()
         .filter(|f| !used_metadata.contains(&f.archive_path))
@@ -0,0 +1,456 @@
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+use tar::Archive as TarArchive;
+use zip::ZipArchive;
+
+/// Error type for archive source operations
+#[derive(Debug)]
+pub enum SourceError {
+    /// Reading from the underlying file or directory failed
+    IoError(String),
+    /// The container itself could not be decoded
+    ArchiveError(String),
+    /// The path does not point to a supported source
+    Unsupported(String),
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceError::IoError(msg) => write!(f, "IO error: {}", msg),
+            SourceError::ArchiveError(msg) => write!(f, "Archive error: {}", msg),
+            SourceError::Unsupported(msg) => write!(f, "Unsupported source: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// A regular file enumerated from an [`ArchiveSource`]
+#[derive(Debug, Clone)]
+pub struct SourceEntry {
+    /// The path of the entry within the source, always using forward slashes
+    /// (e.g., "Takeout/Google Photos/Album/photo.jpg")
+    pub path: String,
+    /// Handle used to open the entry again through a [`SourceReader`]
+    pub index: usize,
+    /// File size in bytes
+    pub size: u64,
+    /// When the entry was last modified, as a Unix timestamp, if the source records it
+    pub modified: Option<i64>,
+}
+
+/// Callback invoked for every entry when reading a source sequentially
+pub type EntryVisitor<'v> = dyn FnMut(&SourceEntry, &mut dyn Read) -> io::Result<()> + 'v;
+
+/// A container that Takeout files can be read from, such as a zip archive,
+/// a gzipped tarball or an already-extracted directory.
+///
+/// Implement this to teach the tool about new container formats.
+pub trait ArchiveSource: Send + Sync + std::fmt::Debug {
+    /// The path identifying this source on disk
+    fn path(&self) -> &Path;
+
+    /// Whether entries can be opened in any order through [`ArchiveSource::open_reader`].
+    /// Sources without random access are read front to back using [`ArchiveSource::for_each_entry`].
+    fn supports_random_access(&self) -> bool;
+
+    /// Enumerates all regular files in the source
+    fn entries(&self) -> Result<Vec<SourceEntry>, SourceError>;
+
+    /// Opens a reader that reads entries by their handle. Sources without random access
+    /// return [`SourceError::Unsupported`] and override [`ArchiveSource::for_each_entry`].
+    fn open_reader(&self) -> Result<Box<dyn SourceReader + '_>, SourceError>;
+
+    /// Visits every regular file in the source in storage order.
+    /// The visitor must consume as much of the reader as it needs before returning.
+    fn for_each_entry(&self, visit: &mut EntryVisitor<'_>) -> Result<(), SourceError> {
+        let mut reader = self.open_reader()?;
+        for entry in self.entries()? {
+            let data = reader.read_entry(entry.index)?;
+            visit(&entry, &mut data.as_slice())
+                .map_err(|e| SourceError::IoError(format!("Failed to read entry: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// An open handle onto an [`ArchiveSource`]
+pub trait SourceReader {
+    /// Reads the full contents of the entry with the given handle
+    fn read_entry(&mut self, index: usize) -> Result<Vec<u8>, SourceError>;
+}
+
+/// Opens the appropriate source for a path based on its type and extension
+pub fn open_source(path: &Path) -> Result<Box<dyn ArchiveSource>, SourceError> {
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+
+    if path.is_dir() {
+        Ok(Box::new(DirectorySource::new(path.to_path_buf())))
+    } else if file_name.ends_with(".zip") {
+        Ok(Box::new(ZipSource::new(path.to_path_buf())))
+    } else if file_name.ends_with(".tar.gz") {
+        Ok(Box::new(TarGzSource::new(path.to_path_buf())))
+    } else {
+        Err(SourceError::Unsupported(format!(
+            "Unsupported archive format: {}",
+            file_name
+        )))
+    }
+}
+
+/// A `.zip` archive, which supports random access through its central directory
+#[derive(Debug)]
+pub struct ZipSource {
+    path: PathBuf,
+}
+
+impl ZipSource {
+    /// Creates a new ZipSource for the archive at `path`
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn open_archive(&self) -> Result<ZipArchive<BufReader<File>>, SourceError> {
+        let file = File::open(&self.path)
+            .map_err(|e| SourceError::IoError(format!("Failed to open archive: {}", e)))?;
+        ZipArchive::new(BufReader::new(file))
+            .map_err(|e| SourceError::ArchiveError(format!("Failed to read zip archive: {}", e)))
+    }
+}
+
+impl ArchiveSource for ZipSource {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn supports_random_access(&self) -> bool {
+        true
+    }
+
+    fn entries(&self) -> Result<Vec<SourceEntry>, SourceError> {
+        let mut archive = self.open_archive()?;
+        let mut entries = Vec::new();
+        for i in 0..archive.len() {
+            let entry = archive
+                .by_index(i)
+                .map_err(|e| SourceError::ArchiveError(format!("Failed to read entry: {}", e)))?;
+            if entry.is_dir() {
+                continue;
+            }
+            entries.push(SourceEntry {
+                path: entry.name().to_string(),
+                index: i,
+                size: entry.size(),
+                modified: entry.last_modified().and_then(zip_timestamp),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn open_reader(&self) -> Result<Box<dyn SourceReader + '_>, SourceError> {
+        Ok(Box::new(ZipReader {
+            archive: self.open_archive()?,
+        }))
+    }
+}
+
+/// Converts the time of a zip entry to a Unix timestamp. Zip archives store local time without
+/// an offset, and Takeout's are written in UTC.
+fn zip_timestamp(time: zip::DateTime) -> Option<i64> {
+    NaiveDate::from_ymd_opt(time.year().into(), time.month().into(), time.day().into())?
+        .and_hms_opt(time.hour().into(), time.minute().into(), time.second().into())
+        .map(|datetime| datetime.and_utc().timestamp())
+}
+
+struct ZipReader {
+    archive: ZipArchive<BufReader<File>>,
+}
+
+impl SourceReader for ZipReader {
+    fn read_entry(&mut self, index: usize) -> Result<Vec<u8>, SourceError> {
+        let mut entry = self
+            .archive
+            .by_index(index)
+            .map_err(|e| SourceError::ArchiveError(format!("Failed to read entry: {}", e)))?;
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|e| SourceError::IoError(format!("Failed to read file contents: {}", e)))?;
+        Ok(contents)
+    }
+}
+
+/// A `.tar.gz` archive, which can only be read sequentially
+#[derive(Debug)]
+pub struct TarGzSource {
+    path: PathBuf,
+}
+
+impl TarGzSource {
+    /// Creates a new TarGzSource for the archive at `path`
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Walks all regular files in the archive, numbering them by their position in the stream
+    fn walk(
+        &self,
+        visit: &mut dyn FnMut(SourceEntry, &mut dyn Read) -> Result<bool, SourceError>,
+    ) -> Result<(), SourceError> {
+        let file = File::open(&self.path)
+            .map_err(|e| SourceError::IoError(format!("Failed to open archive: {}", e)))?;
+        let mut archive = TarArchive::new(GzDecoder::new(BufReader::new(file)));
+        let entries = archive
+            .entries()
+            .map_err(|e| SourceError::ArchiveError(format!("Failed to read tar entries: {}", e)))?;
+
+        for (index, entry) in entries.enumerate() {
+            let mut entry = entry
+                .map_err(|e| SourceError::ArchiveError(format!("Failed to read entry: {}", e)))?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let entry_path = entry
+                .path()
+                .map_err(|e| SourceError::ArchiveError(format!("Failed to get path: {}", e)))?;
+            let mut entry_path_str = entry_path.to_string_lossy().to_string();
+            if let Some(stripped) = entry_path_str.strip_prefix("./") {
+                entry_path_str = stripped.to_string();
+            }
+
+            let source_entry = SourceEntry {
+                path: entry_path_str,
+                index,
+                size: entry.size(),
+                modified: entry.header().mtime().ok().and_then(|t| i64::try_from(t).ok()),
+            };
+            if !visit(source_entry, &mut entry)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ArchiveSource for TarGzSource {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn supports_random_access(&self) -> bool {
+        false
+    }
+
+    fn entries(&self) -> Result<Vec<SourceEntry>, SourceError> {
+        let mut entries = Vec::new();
+        self.walk(&mut |entry, _| {
+            entries.push(entry);
+            Ok(true)
+        })?;
+        Ok(entries)
+    }
+
+    fn open_reader(&self) -> Result<Box<dyn SourceReader + '_>, SourceError> {
+        Err(SourceError::Unsupported(format!(
+            "{} can only be read front to back",
+            self.path.display()
+        )))
+    }
+
+    fn for_each_entry(&self, visit: &mut EntryVisitor<'_>) -> Result<(), SourceError> {
+        self.walk(&mut |entry, reader| {
+            visit(&entry, reader)
+                .map_err(|e| SourceError::IoError(format!("Failed to read entry: {}", e)))?;
+            Ok(true)
+        })
+    }
+}
+
+/// An already-extracted takeout on the filesystem, i.e. a directory containing a `Takeout` folder
+#[derive(Debug)]
+pub struct DirectorySource {
+    path: PathBuf,
+    /// The files below `path`, listed once and shared by all readers
+    entries: OnceLock<Vec<SourceEntry>>,
+}
+
+impl DirectorySource {
+    /// Creates a new DirectorySource rooted at `path`
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            entries: OnceLock::new(),
+        }
+    }
+
+    /// Returns the files below the directory, walking it on first use only
+    fn cached_entries(&self) -> Result<&[SourceEntry], SourceError> {
+        if let Some(entries) = self.entries.get() {
+            return Ok(entries);
+        }
+        let entries = self.walk()?;
+        Ok(self.entries.get_or_init(|| entries))
+    }
+
+    /// Walks the directory, numbering its files in path order
+    fn walk(&self) -> Result<Vec<SourceEntry>, SourceError> {
+        let mut files = Vec::new();
+        let mut stack = vec![self.path.clone()];
+        while let Some(dir) = stack.pop() {
+            let dir_entries = fs::read_dir(&dir)
+                .map_err(|e| SourceError::IoError(format!("Failed to read directory: {}", e)))?;
+            for entry in dir_entries {
+                let entry = entry
+                    .map_err(|e| SourceError::IoError(format!("Failed to read entry: {}", e)))?;
+                let file_type = entry
+                    .file_type()
+                    .map_err(|e| SourceError::IoError(format!("Failed to read entry: {}", e)))?;
+
+                if file_type.is_dir() {
+                    stack.push(entry.path());
+                    continue;
+                }
+                if !file_type.is_file() {
+                    continue;
+                }
+
+                let metadata = entry
+                    .metadata()
+                    .map_err(|e| SourceError::IoError(format!("Failed to read entry: {}", e)))?;
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .and_then(|since_epoch| i64::try_from(since_epoch.as_secs()).ok());
+                files.push((entry.path(), metadata.len(), modified));
+            }
+        }
+
+        // Sort so handles follow the order of the paths
+        files.sort();
+
+        files
+            .into_iter()
+            .enumerate()
+            .map(|(index, (path, size, modified))| {
+                // Entry paths always use forward slashes, regardless of platform
+                let relative = path
+                    .strip_prefix(&self.path)
+                    .map_err(|e| SourceError::IoError(format!("Failed to get path: {}", e)))?;
+                let entry_path = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                Ok(SourceEntry {
+                    path: entry_path,
+                    index,
+                    size,
+                    modified,
+                })
+            })
+            .collect()
+    }
+}
+
+impl ArchiveSource for DirectorySource {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn supports_random_access(&self) -> bool {
+        true
+    }
+
+    fn entries(&self) -> Result<Vec<SourceEntry>, SourceError> {
+        self.cached_entries().map(<[SourceEntry]>::to_vec)
+    }
+
+    fn open_reader(&self) -> Result<Box<dyn SourceReader + '_>, SourceError> {
+        Ok(Box::new(DirectoryReader {
+            files: self.cached_entries()?,
+            root: &self.path,
+        }))
+    }
+}
+
+struct DirectoryReader<'a> {
+    root: &'a Path,
+    files: &'a [SourceEntry],
+}
+
+impl SourceReader for DirectoryReader<'_> {
+    fn read_entry(&mut self, index: usize) -> Result<Vec<u8>, SourceError> {
+        let entry = self
+            .files
+            .get(index)
+            .ok_or_else(|| SourceError::IoError(format!("No entry with index {}", index)))?;
+        let path = self.root.join(&entry.path);
+        fs::read(&path)
+            .map_err(|e| SourceError::IoError(format!("Failed to read {}: {}", path.display(), e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decompressed_takeout() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test_data")
+            .join("input_decompressed")
+            .join("takeout-20260102T143355Z-3-002")
+    }
+
+    #[test]
+    fn test_directory_source_entries() {
+        let source = DirectorySource::new(decompressed_takeout());
+        let entries = source.entries().unwrap();
+
+        assert!(source.supports_random_access());
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].modified.is_some());
+        assert_eq!(
+            entries[0].path,
+            "Takeout/Google Photos/Album 2/2013-12-02 14.07.52.jpg.supplemental-metadata.json"
+        );
+    }
+
+    #[test]
+    fn test_directory_source_read_entry() {
+        let source = DirectorySource::new(decompressed_takeout());
+        let entries = source.entries().unwrap();
+        let mut reader = source.open_reader().unwrap();
+
+        let data = reader.read_entry(entries[0].index).unwrap();
+        assert_eq!(data.len() as u64, entries[0].size);
+
+        let mut visited = Vec::new();
+        source
+            .for_each_entry(&mut |entry, _| {
+                visited.push(entry.path.clone());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(visited, vec![entries[0].path.clone()]);
+    }
+
+    #[test]
+    fn test_tar_gz_source_has_no_random_access() {
+        let source = TarGzSource::new(PathBuf::from("takeout.tar.gz"));
+        assert!(!source.supports_random_access());
+        assert!(matches!(source.open_reader(), Err(SourceError::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_open_source_rejects_unknown_format() {
+        assert!(open_source(Path::new("archive.rar")).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+use std::sync::{Mutex, MutexGuard};
+
+/// Locks a mutex, ignoring poisoning since a panicking worker cannot leave our state half-updated
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,170 @@
+use chrono::{DateTime, FixedOffset, Offset, TimeZone};
+use chrono_tz::Tz;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use tzf_rs::DefaultFinder;
+
+/// A timezone to convert capture times to local time with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    /// An IANA timezone, with daylight saving time
+    Named(Tz),
+    /// A fixed offset from UTC
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    /// Returns the offset from UTC in seconds at a Unix timestamp
+    pub fn offset_at(&self, timestamp: i64) -> i32 {
+        match self {
+            Timezone::Named(tz) => DateTime::from_timestamp(timestamp, 0)
+                .map(|utc| tz.offset_from_utc_datetime(&utc.naive_utc()).fix())
+                .map(|offset| offset.local_minus_utc())
+                .unwrap_or(0),
+            Timezone::Fixed(offset) => offset.local_minus_utc(),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    /// Parses an IANA name like "Europe/Copenhagen" or an offset like "+02:00" or "-0530"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('+') || s.starts_with('-') {
+            return parse_offset(s).map(Timezone::Fixed);
+        }
+        Tz::from_str(s).map(Timezone::Named).map_err(|_| {
+            format!(
+                "Unknown timezone '{}', expected a name like \"Europe/Copenhagen\" or an offset like \"+02:00\"",
+                s
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for Timezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timezone::Named(tz) => write!(f, "{}", tz.name()),
+            Timezone::Fixed(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+/// Parses a UTC offset of the form "+HH", "+HH:MM" or "+HHMM"
+fn parse_offset(s: &str) -> Result<FixedOffset, String> {
+    let invalid = || format!("Invalid UTC offset '{}', expected e.g. \"+02:00\"", s);
+    let (sign, digits) = s.split_at(1);
+    let digits = digits.replace(':', "");
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (&digits[..], "0"),
+        4 => digits.split_at(2),
+        _ => return Err(invalid()),
+    };
+    let seconds = hours.parse::<i32>().map_err(|_| invalid())? * 3600
+        + minutes.parse::<i32>().map_err(|_| invalid())? * 60;
+    let seconds = if sign == "-" { -seconds } else { seconds };
+    FixedOffset::east_opt(seconds).ok_or_else(invalid)
+}
+
+/// Formats an offset in seconds the way EXIF `OffsetTime*` tags store it, e.g. "+05:30"
+pub fn format_exif_offset(offset_seconds: i32) -> String {
+    let sign = if offset_seconds < 0 { '-' } else { '+' };
+    let minutes = offset_seconds.unsigned_abs() / 60;
+    format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Timezone boundaries, which take a moment to load, so they are only loaded once needed
+static TIMEZONE_FINDER: LazyLock<DefaultFinder> = LazyLock::new(DefaultFinder::new);
+
+/// Finds the timezone of a location from the embedded timezone boundaries. Locations outside
+/// every boundary get the nautical offset of their longitude.
+pub fn timezone_for_location(latitude: f64, longitude: f64) -> Timezone {
+    if let Ok(tz) = Tz::from_str(TIMEZONE_FINDER.get_tz_name(longitude, latitude)) {
+        return Timezone::Named(tz);
+    }
+
+    let hours = (longitude / 15.0).round().clamp(-12.0, 12.0) as i32;
+    FixedOffset::east_opt(hours * 3600)
+        .map(Timezone::Fixed)
+        .unwrap_or(Timezone::Named(Tz::UTC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timezone_for_location() {
+        // Tokyo Tower
+        assert_eq!(
+            timezone_for_location(35.6586, 139.7454),
+            Timezone::Named(chrono_tz::Asia::Tokyo)
+        );
+        // Lake Balaton
+        assert_eq!(
+            timezone_for_location(46.7234, 17.3456).offset_at(1563032119),
+            2 * 3600
+        );
+        // Grand Canyon, which does not observe daylight saving time
+        assert_eq!(
+            timezone_for_location(36.0544, -112.1401),
+            Timezone::Named(chrono_tz::America::Phoenix)
+        );
+        // The middle of the Atlantic gets a nautical offset
+        assert_eq!(timezone_for_location(30.0, -40.0).offset_at(0), -3 * 3600);
+    }
+
+    #[test]
+    fn test_timezone_for_location_near_borders() {
+        let name = |latitude, longitude| timezone_for_location(latitude, longitude).to_string();
+        // Calais, across the Channel from Dover
+        assert_eq!(name(50.9513, 1.8587), "Europe/Paris");
+        assert_eq!(name(51.1279, 1.3134), "Europe/London");
+        // El Paso and Ciudad Juárez, across the Rio Grande
+        assert_eq!(name(31.7619, -106.4850), "America/Denver");
+        assert_eq!(name(31.6904, -106.4245), "America/Ciudad_Juarez");
+        // Narva and Ivangorod, across the Narva river
+        assert_eq!(name(59.3772, 28.1903), "Europe/Tallinn");
+        assert_eq!(name(59.3667, 28.2167), "Europe/Moscow");
+        // Kashgar and Osh, either side of the border between China and Kyrgyzstan
+        assert_eq!(name(39.4704, 75.9898), "Asia/Shanghai");
+        assert_eq!(name(40.5283, 72.7985), "Asia/Bishkek");
+    }
+
+    #[test]
+    fn test_offset_at_daylight_saving_time() {
+        let tz: Timezone = "Europe/Copenhagen".parse().unwrap();
+        // 2019-07-13 15:35:19 UTC and 2019-01-13 15:35:19 UTC
+        assert_eq!(tz.offset_at(1563032119), 7200);
+        assert_eq!(tz.offset_at(1547393719), 3600);
+    }
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!(
+            "Asia/Kolkata".parse::<Timezone>().unwrap().offset_at(0),
+            5 * 3600 + 1800
+        );
+        assert_eq!(
+            "+05:30".parse::<Timezone>().unwrap().offset_at(0),
+            5 * 3600 + 1800
+        );
+        assert_eq!("-0300".parse::<Timezone>().unwrap().offset_at(0), -3 * 3600);
+        assert_eq!("+09".parse::<Timezone>().unwrap().offset_at(0), 9 * 3600);
+        assert!("Mars/Olympus_Mons".parse::<Timezone>().is_err());
+        assert!("+5:30".parse::<Timezone>().is_err());
+        assert!("+25:00".parse::<Timezone>().is_err());
+    }
+
+    #[test]
+    fn test_format_exif_offset() {
+        assert_eq!(format_exif_offset(0), "+00:00");
+        assert_eq!(format_exif_offset(5 * 3600 + 1800), "+05:30");
+        assert_eq!(format_exif_offset(-(9 * 3600 + 1800)), "-09:30");
+    }
+}
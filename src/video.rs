@@ -0,0 +1,576 @@
+use crate::metadata::{GeoData, GoogleSupplementalMetadata};
+use std::slice::ChunksExactMut;
+
+/// Seconds between the QuickTime epoch (1904-01-01) and the Unix epoch (1970-01-01)
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Boxes on the path from `moov` to the boxes we modify
+const CONTAINER_BOXES: &[&[u8; 4]] = &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"udta"];
+
+/// QuickTime language code for "und" (undetermined), used by the `©xyz` atom
+const UNDETERMINED_LANGUAGE: u16 = 0x55c4;
+
+/// Error type for video metadata operations
+#[derive(Debug)]
+pub enum VideoError {
+    /// The file is not a well-formed MP4/QuickTime container
+    Malformed(String),
+    /// The file is valid but uses a layout we cannot rewrite safely
+    Unsupported(String),
+}
+
+impl std::fmt::Display for VideoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoError::Malformed(msg) => write!(f, "Malformed video: {}", msg),
+            VideoError::Unsupported(msg) => write!(f, "Unsupported video: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for VideoError {}
+
+/// A box (atom) of an MP4/QuickTime file. Only the containers we need to descend into are
+/// parsed, everything else is kept as opaque payload bytes.
+#[derive(Debug)]
+struct Mp4Box {
+    kind: [u8; 4],
+    content: BoxContent,
+}
+
+#[derive(Debug)]
+enum BoxContent {
+    Leaf(Vec<u8>),
+    Container(Vec<Mp4Box>),
+}
+
+/// Position of a top-level box within the file
+struct BoxRange {
+    kind: [u8; 4],
+    start: usize,
+    end: usize,
+}
+
+/// Reads the header of the box starting at `offset`, returning its type, the offset of its
+/// payload and the offset just past its end
+fn read_box_header(data: &[u8], offset: usize) -> Result<([u8; 4], usize, usize), VideoError> {
+    let header = data
+        .get(offset..offset + 8)
+        .ok_or_else(|| VideoError::Malformed(format!("truncated box header at {}", offset)))?;
+    let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    let kind = [header[4], header[5], header[6], header[7]];
+
+    let (payload_start, size) = match size {
+        // The box extends to the end of the file
+        0 => (offset + 8, (data.len() - offset) as u64),
+        // A 64-bit size follows the type
+        1 => {
+            let large = data.get(offset + 8..offset + 16).ok_or_else(|| {
+                VideoError::Malformed(format!("truncated 64-bit box size at {}", offset))
+            })?;
+            let large: [u8; 8] = large.try_into().unwrap_or_default();
+            (offset + 16, u64::from_be_bytes(large))
+        }
+        size => (offset + 8, size),
+    };
+
+    let end = usize::try_from(size)
+        .ok()
+        .and_then(|size| offset.checked_add(size))
+        .filter(|&end| end >= payload_start && end <= data.len())
+        .ok_or_else(|| {
+            VideoError::Malformed(format!(
+                "box '{}' at {} has invalid size {}",
+                String::from_utf8_lossy(&kind),
+                offset,
+                size
+            ))
+        })?;
+
+    Ok((kind, payload_start, end))
+}
+
+/// Lists the top-level boxes of a file without reading their payloads
+fn top_level_boxes(data: &[u8]) -> Result<Vec<BoxRange>, VideoError> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let (kind, _, end) = read_box_header(data, offset)?;
+        boxes.push(BoxRange {
+            kind,
+            start: offset,
+            end,
+        });
+        offset = end;
+    }
+    Ok(boxes)
+}
+
+/// Parses a sequence of boxes, descending into [`CONTAINER_BOXES`]. With `allow_padding`,
+/// trailing bytes too short to be a box are dropped, like the 32-bit zero terminator that
+/// QuickTime allows at the end of `udta`.
+fn parse_boxes(data: &[u8], allow_padding: bool) -> Result<Vec<Mp4Box>, VideoError> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        if allow_padding && data.len() - offset < 8 {
+            break;
+        }
+        let (kind, payload_start, end) = read_box_header(data, offset)?;
+        let payload = &data[payload_start..end];
+        let content = if CONTAINER_BOXES.contains(&&kind) {
+            BoxContent::Container(parse_boxes(payload, &kind == b"udta")?)
+        } else {
+            BoxContent::Leaf(payload.to_vec())
+        };
+        boxes.push(Mp4Box { kind, content });
+        offset = end;
+    }
+    Ok(boxes)
+}
+
+impl Mp4Box {
+    fn payload_len(&self) -> usize {
+        match &self.content {
+            BoxContent::Leaf(payload) => payload.len(),
+            BoxContent::Container(children) => children.iter().map(Mp4Box::len).sum(),
+        }
+    }
+
+    /// The serialized length of the box including its header
+    fn len(&self) -> usize {
+        let payload_len = self.payload_len();
+        if payload_len + 8 > u32::MAX as usize {
+            payload_len + 16
+        } else {
+            payload_len + 8
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let len = self.len();
+        if len > u32::MAX as usize {
+            out.extend_from_slice(&1u32.to_be_bytes());
+            out.extend_from_slice(&self.kind);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        } else {
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+            out.extend_from_slice(&self.kind);
+        }
+        match &self.content {
+            BoxContent::Leaf(payload) => out.extend_from_slice(payload),
+            BoxContent::Container(children) => {
+                for child in children {
+                    child.write(out);
+                }
+            }
+        }
+    }
+
+    /// Calls `f` on the payload of every leaf box of the given type in this subtree
+    fn for_each_leaf(
+        &mut self,
+        kind: &[u8; 4],
+        f: &mut impl FnMut(&mut Vec<u8>) -> Result<(), VideoError>,
+    ) -> Result<(), VideoError> {
+        match &mut self.content {
+            BoxContent::Leaf(payload) if &self.kind == kind => f(payload),
+            BoxContent::Leaf(_) => Ok(()),
+            BoxContent::Container(children) => {
+                for child in children {
+                    child.for_each_leaf(kind, f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Converts a Unix timestamp to seconds since the QuickTime epoch
+fn to_mp4_time(timestamp: i64) -> Option<u64> {
+    timestamp
+        .checked_add(MP4_EPOCH_OFFSET)
+        .and_then(|t| u64::try_from(t).ok())
+}
+
+/// Sets the creation and modification time of an `mvhd`, `tkhd` or `mdhd` payload.
+/// Version 0 boxes only hold 32-bit times, so dates after 2040 leave them untouched.
+fn set_header_times(payload: &mut [u8], time: u64) -> Result<(), VideoError> {
+    let version = *payload
+        .first()
+        .ok_or_else(|| VideoError::Malformed("empty media header".to_string()))?;
+    match version {
+        0 => {
+            let Ok(time) = u32::try_from(time) else {
+                return Ok(());
+            };
+            let times = payload
+                .get_mut(4..12)
+                .ok_or_else(|| VideoError::Malformed("truncated media header".to_string()))?;
+            times[..4].copy_from_slice(&time.to_be_bytes());
+            times[4..].copy_from_slice(&time.to_be_bytes());
+        }
+        1 => {
+            let times = payload
+                .get_mut(4..20)
+                .ok_or_else(|| VideoError::Malformed("truncated media header".to_string()))?;
+            times[..8].copy_from_slice(&time.to_be_bytes());
+            times[8..].copy_from_slice(&time.to_be_bytes());
+        }
+        v => {
+            return Err(VideoError::Unsupported(format!(
+                "media header version {}",
+                v
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Formats a location as an ISO 6709 string the way QuickTime stores it, e.g.
+/// "+46.7234+017.3456+150.500/"
+fn format_iso6709(geo: &GeoData) -> String {
+    let mut location = format!("{:+08.4}{:+09.4}", geo.latitude, geo.longitude);
+    if geo.altitude != 0.0 {
+        location.push_str(&format!("{:+.3}", geo.altitude));
+    }
+    location.push('/');
+    location
+}
+
+/// Builds the payload of a `©xyz` user data atom
+fn location_atom(geo: &GeoData) -> Mp4Box {
+    let location = format_iso6709(geo);
+    let mut payload = Vec::with_capacity(location.len() + 4);
+    payload.extend_from_slice(&(location.len() as u16).to_be_bytes());
+    payload.extend_from_slice(&UNDETERMINED_LANGUAGE.to_be_bytes());
+    payload.extend_from_slice(location.as_bytes());
+    Mp4Box {
+        kind: *b"\xa9xyz",
+        content: BoxContent::Leaf(payload),
+    }
+}
+
+/// Replaces any `©xyz` atom in `moov/udta`, creating `udta` if needed
+fn set_location(moov: &mut Mp4Box, geo: &GeoData) -> Result<(), VideoError> {
+    let BoxContent::Container(children) = &mut moov.content else {
+        return Err(VideoError::Malformed(
+            "'moov' is not a container".to_string(),
+        ));
+    };
+    if !children.iter().any(|b| &b.kind == b"udta") {
+        children.push(Mp4Box {
+            kind: *b"udta",
+            content: BoxContent::Container(Vec::new()),
+        });
+    }
+    let udta = children
+        .iter_mut()
+        .find(|b| &b.kind == b"udta")
+        .ok_or_else(|| VideoError::Malformed("missing 'udta'".to_string()))?;
+    let BoxContent::Container(atoms) = &mut udta.content else {
+        return Err(VideoError::Malformed(
+            "'udta' is not a container".to_string(),
+        ));
+    };
+    atoms.retain(|atom| &atom.kind != b"\xa9xyz");
+    atoms.push(location_atom(geo));
+    Ok(())
+}
+
+/// Shifts the chunk offsets in `stco` and `co64` boxes that point at or past `from`
+fn shift_chunk_offsets(moov: &mut Mp4Box, from: u64, delta: i64) -> Result<(), VideoError> {
+    let shift = |offset: u64| -> Result<u64, VideoError> {
+        if offset < from {
+            return Ok(offset);
+        }
+        offset
+            .checked_add_signed(delta)
+            .ok_or_else(|| VideoError::Malformed(format!("chunk offset {} out of range", offset)))
+    };
+
+    moov.for_each_leaf(b"stco", &mut |payload| {
+        for entry in chunk_offset_entries(payload, 4)? {
+            let offset = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as u64;
+            let shifted = u32::try_from(shift(offset)?).map_err(|_| {
+                VideoError::Unsupported("chunk offsets no longer fit in 'stco'".to_string())
+            })?;
+            entry.copy_from_slice(&shifted.to_be_bytes());
+        }
+        Ok(())
+    })?;
+    moov.for_each_leaf(b"co64", &mut |payload| {
+        for entry in chunk_offset_entries(payload, 8)? {
+            let offset = u64::from_be_bytes((&*entry).try_into().unwrap_or_default());
+            entry.copy_from_slice(&shift(offset)?.to_be_bytes());
+        }
+        Ok(())
+    })
+}
+
+/// Returns the entries of a `stco`/`co64` payload, each `width` bytes wide
+fn chunk_offset_entries(
+    payload: &mut [u8],
+    width: usize,
+) -> Result<ChunksExactMut<'_, u8>, VideoError> {
+    let count = payload
+        .get(4..8)
+        .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]) as usize)
+        .ok_or_else(|| VideoError::Malformed("truncated chunk offset table".to_string()))?;
+    count
+        .checked_mul(width)
+        .and_then(|len| payload.get_mut(8..8 + len))
+        .map(|entries| entries.chunks_exact_mut(width))
+        .ok_or_else(|| VideoError::Malformed("truncated chunk offset table".to_string()))
+}
+
+/// Writes the capture time and location from Google metadata into an MP4/QuickTime file.
+/// The `mvhd`, `tkhd` and `mdhd` creation and modification times are set to
+/// `photoTakenTime` and the location is stored as a `©xyz` atom in `moov/udta`.
+/// When the `moov` box precedes the media data, the chunk offsets are patched to account
+/// for its new size.
+pub fn apply_google_metadata_to_video(
+    data: &[u8],
+    google_meta: &GoogleSupplementalMetadata,
+) -> Result<Vec<u8>, VideoError> {
+    let boxes = top_level_boxes(data)?;
+    let moov_range = boxes
+        .iter()
+        .find(|b| &b.kind == b"moov")
+        .ok_or_else(|| VideoError::Malformed("no 'moov' box".to_string()))?;
+
+    let (_, payload_start, _) = read_box_header(data, moov_range.start)?;
+    let mut moov = Mp4Box {
+        kind: *b"moov",
+        content: BoxContent::Container(parse_boxes(&data[payload_start..moov_range.end], false)?),
+    };
+
+    // Invalid capture times are reported by the caller, so they are simply skipped here
+    if let Some(time) = google_meta.taken_timestamp().ok().flatten().and_then(to_mp4_time) {
+        for kind in [b"mvhd", b"tkhd", b"mdhd"] {
+            moov.for_each_leaf(kind, &mut |payload| set_header_times(payload, time))?;
+        }
+    }
+
+    if let Some(geo) = google_meta.location() {
+        set_location(&mut moov, geo)?;
+    }
+
+    // Media data stored after the moov box moves by however much the moov box grew
+    let delta = moov.len() as i64 - (moov_range.end - moov_range.start) as i64;
+    if delta != 0 {
+        if boxes
+            .iter()
+            .any(|b| b.start >= moov_range.end && &b.kind == b"moof")
+        {
+            return Err(VideoError::Unsupported(
+                "fragmented video with movie fragments after 'moov'".to_string(),
+            ));
+        }
+        shift_chunk_offsets(&mut moov, moov_range.end as u64, delta)?;
+    }
+
+    let mut output = Vec::with_capacity(data.len() + delta.max(0) as usize);
+    output.extend_from_slice(&data[..moov_range.start]);
+    moov.write(&mut output);
+    output.extend_from_slice(&data[moov_range.end..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::parse_google_metadata;
+
+    const SAMPLE_JSON: &str = r#"{
+        "title": "VID_0001.mp4",
+        "photoTakenTime": {
+            "timestamp": "1563032119",
+            "formatted": "13. jul. 2019, 15.35.19 UTC"
+        },
+        "geoData": {
+            "latitude": 46.7234,
+            "longitude": -17.3456,
+            "altitude": 150.5,
+            "latitudeSpan": 0.0,
+            "longitudeSpan": 0.0
+        }
+    }"#;
+
+    const MEDIA: &[u8] = b"frame data";
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// A version 0 `mvhd`/`tkhd`/`mdhd` payload with zeroed times
+    fn header_v0() -> Vec<u8> {
+        vec![0; 24]
+    }
+
+    fn stco(offset: u32) -> Vec<u8> {
+        let mut payload = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        payload.extend_from_slice(&offset.to_be_bytes());
+        mp4_box(b"stco", &payload)
+    }
+
+    fn moov(chunk_offset: u32, udta: &[u8]) -> Vec<u8> {
+        let stbl = mp4_box(b"stbl", &stco(chunk_offset));
+        let minf = mp4_box(b"minf", &stbl);
+        let mdia = [mp4_box(b"mdhd", &header_v0()), minf].concat();
+        let trak = [mp4_box(b"tkhd", &header_v0()), mp4_box(b"mdia", &mdia)].concat();
+        let moov = [
+            mp4_box(b"mvhd", &header_v0()),
+            mp4_box(b"trak", &trak),
+            udta.to_vec(),
+        ]
+        .concat();
+        mp4_box(b"moov", &moov)
+    }
+
+    /// Builds a file with the `moov` box either before or after `mdat`
+    fn sample_video(moov_first: bool, udta: &[u8]) -> Vec<u8> {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let mdat = mp4_box(b"mdat", MEDIA);
+        if moov_first {
+            let moov_len = moov(0, udta).len();
+            let offset = (ftyp.len() + moov_len + 8) as u32;
+            [ftyp, moov(offset, udta), mdat].concat()
+        } else {
+            let offset = (ftyp.len() + 8) as u32;
+            [ftyp, mdat, moov(offset, udta)].concat()
+        }
+    }
+
+    /// Finds the payload of the first box of a type, searching the given containers
+    fn find_payload<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        let mut offset = 0;
+        while offset < data.len() {
+            let (kind, payload_start, end) = read_box_header(data, offset).unwrap();
+            if &kind == path[0] {
+                let payload = &data[payload_start..end];
+                return if path.len() == 1 {
+                    payload
+                } else {
+                    find_payload(payload, &path[1..])
+                };
+            }
+            offset = end;
+        }
+        panic!("box {:?} not found", String::from_utf8_lossy(path[0]));
+    }
+
+    fn chunk_offset(video: &[u8]) -> usize {
+        let stco = find_payload(
+            video,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stco"],
+        );
+        u32::from_be_bytes(stco[8..12].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn test_apply_metadata_moov_first() {
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let input = sample_video(true, &[]);
+        assert_eq!(&input[chunk_offset(&input)..][..MEDIA.len()], MEDIA);
+
+        let output = apply_google_metadata_to_video(&input, &meta).unwrap();
+
+        let expected_time = (1563032119 + MP4_EPOCH_OFFSET) as u32;
+        for path in [
+            &[b"moov", b"mvhd"][..],
+            &[b"moov", b"trak", b"tkhd"],
+            &[b"moov", b"trak", b"mdia", b"mdhd"],
+        ] {
+            let header = find_payload(&output, path);
+            assert_eq!(
+                u32::from_be_bytes(header[4..8].try_into().unwrap()),
+                expected_time
+            );
+            assert_eq!(
+                u32::from_be_bytes(header[8..12].try_into().unwrap()),
+                expected_time
+            );
+        }
+
+        let xyz = find_payload(&output, &[b"moov", b"udta", b"\xa9xyz"]);
+        assert_eq!(&xyz[4..], b"+46.7234-017.3456+150.500/");
+        assert_eq!(u16::from_be_bytes([xyz[0], xyz[1]]) as usize, xyz.len() - 4);
+
+        // The media data moved, and the chunk offset still points at it
+        assert!(output.len() > input.len());
+        assert_eq!(&output[chunk_offset(&output)..][..MEDIA.len()], MEDIA);
+    }
+
+    #[test]
+    fn test_apply_metadata_moov_last() {
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let input = sample_video(false, &[]);
+        let output = apply_google_metadata_to_video(&input, &meta).unwrap();
+
+        assert_eq!(chunk_offset(&output), chunk_offset(&input));
+        assert_eq!(&output[chunk_offset(&output)..][..MEDIA.len()], MEDIA);
+        find_payload(&output, &[b"moov", b"udta", b"\xa9xyz"]);
+    }
+
+    #[test]
+    fn test_apply_metadata_replaces_location() {
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let old_xyz = mp4_box(b"\xa9xyz", b"\x00\x12\x15\xc7+01.0000+002.0000/");
+        let udta = mp4_box(b"udta", &[mp4_box(b"\xa9nam", b"name"), old_xyz].concat());
+        let input = sample_video(true, &udta);
+
+        let output = apply_google_metadata_to_video(&input, &meta).unwrap();
+        let udta = find_payload(&output, &[b"moov", b"udta"]);
+        let boxes = parse_boxes(udta, true).unwrap();
+        let kinds: Vec<_> = boxes.iter().map(|b| &b.kind).collect();
+        assert_eq!(kinds, [b"\xa9nam", b"\xa9xyz"]);
+        assert_eq!(&output[chunk_offset(&output)..][..MEDIA.len()], MEDIA);
+    }
+
+    #[test]
+    fn test_apply_metadata_udta_with_terminator() {
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let udta = mp4_box(b"udta", &[mp4_box(b"\xa9nam", b"name"), vec![0; 4]].concat());
+        let input = sample_video(true, &udta);
+
+        let output = apply_google_metadata_to_video(&input, &meta).unwrap();
+        let udta = find_payload(&output, &[b"moov", b"udta"]);
+        let boxes = parse_boxes(udta, true).unwrap();
+        let kinds: Vec<_> = boxes.iter().map(|b| &b.kind).collect();
+        assert_eq!(kinds, [b"\xa9nam", b"\xa9xyz"]);
+        assert_eq!(&output[chunk_offset(&output)..][..MEDIA.len()], MEDIA);
+    }
+
+    #[test]
+    fn test_apply_metadata_without_location() {
+        let json = r#"{"title": "VID_0001.mp4", "geoData": {"latitude": 0.0, "longitude": 0.0, "altitude": 0.0}}"#;
+        let meta = parse_google_metadata(json).unwrap();
+        let input = sample_video(true, &[]);
+
+        // Nothing to write, so the file is unchanged
+        let output = apply_google_metadata_to_video(&input, &meta).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_apply_metadata_malformed() {
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        assert!(matches!(
+            apply_google_metadata_to_video(b"not a video", &meta),
+            Err(VideoError::Malformed(_))
+        ));
+        let no_moov = mp4_box(b"mdat", MEDIA);
+        assert!(apply_google_metadata_to_video(&no_moov, &meta).is_err());
+    }
+
+    #[test]
+    fn test_to_mp4_time() {
+        assert_eq!(to_mp4_time(0), Some(MP4_EPOCH_OFFSET as u64));
+        assert_eq!(to_mp4_time(-MP4_EPOCH_OFFSET), Some(0));
+        assert_eq!(to_mp4_time(-MP4_EPOCH_OFFSET - 1), None);
+    }
+}
@@ -0,0 +1,448 @@
+use crate::metadata::{GoogleSupplementalMetadata, MetadataError, MetadataOptions};
+use chrono::{DateTime, FixedOffset};
+use std::path::{Path, PathBuf};
+
+/// Identifies the APP1 segment of a JPEG that holds XMP
+const JPEG_XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Start of an XMP packet, up to the properties of its single `rdf:Description`
+const XMP_HEADER: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"
+    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"
+    xmlns:exif=\"http://ns.adobe.com/exif/1.0/\"
+    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"
+    xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"
+    xmlns:Iptc4xmpExt=\"http://iptc.org/std/Iptc4xmpExt/2008-02-29/\">
+";
+
+/// End of an XMP packet, after the properties
+const XMP_FOOTER: &str = "  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+";
+
+/// Error type for embedding XMP into media files
+#[derive(Debug)]
+pub enum XmpError {
+    /// The file is not well-formed
+    Malformed(String),
+    /// The file is valid but XMP cannot be added to it safely
+    Unsupported(String),
+}
+
+impl std::fmt::Display for XmpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XmpError::Malformed(msg) => write!(f, "Malformed file: {}", msg),
+            XmpError::Unsupported(msg) => write!(f, "Cannot embed XMP: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for XmpError {}
+
+/// Returns the path of the XMP sidecar for a media file, e.g. `photo.jpg.xmp` for `photo.jpg`
+pub fn xmp_sidecar_path(media_path: &Path) -> PathBuf {
+    let mut path = media_path.as_os_str().to_owned();
+    path.push(".xmp");
+    PathBuf::from(path)
+}
+
+/// Escapes text for use in XML element content and attribute values. Control characters other
+/// than tab and line breaks cannot appear in XML at all, so they are left out.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            '\0'..='\x1f' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Formats a coordinate in decimal degrees the way XMP stores GPS coordinates, as degrees and
+/// decimal minutes followed by the reference, e.g. "46,43.404000N"
+fn format_xmp_coordinate(decimal: f64, positive: char, negative: char) -> String {
+    let reference = if decimal >= 0.0 { positive } else { negative };
+    let degrees = decimal.abs().trunc();
+    let minutes = (decimal.abs() - degrees) * 60.0;
+    format!("{},{:.6}{}", degrees, minutes, reference)
+}
+
+/// Builds an XMP packet from Google metadata with the capture time, location, description,
+/// favorite flag and people, for use as a sidecar next to media that is left untouched.
+/// The capture time is written in local time with its offset, like embedded metadata.
+/// `keywords` are added to those from the sidecar, e.g. the name of the album.
+pub fn google_metadata_to_xmp(
+    google_meta: &GoogleSupplementalMetadata,
+    keywords: &[String],
+    options: &MetadataOptions,
+) -> Result<String, MetadataError> {
+    let mut properties = Vec::new();
+
+    if let Some(timestamp) = google_meta.taken_timestamp()? {
+        let offset = google_meta.utc_offset_at(timestamp, options.timezone);
+        let datetime = FixedOffset::east_opt(offset)
+            .zip(DateTime::from_timestamp(timestamp, 0))
+            .map(|(offset, date)| date.with_timezone(&offset).format("%Y-%m-%dT%H:%M:%S%:z"))
+            .ok_or_else(|| {
+                MetadataError::InvalidTimestamp(format!(
+                    "{} cannot be written as an XMP date",
+                    timestamp
+                ))
+            })?;
+        for property in [
+            "exif:DateTimeOriginal",
+            "xmp:CreateDate",
+            "photoshop:DateCreated",
+        ] {
+            properties.push(format!("<{0}>{1}</{0}>", property, datetime));
+        }
+    }
+
+    if let Some(geo) = google_meta.location() {
+        properties.push(format!(
+            "<exif:GPSLatitude>{}</exif:GPSLatitude>",
+            format_xmp_coordinate(geo.latitude, 'N', 'S')
+        ));
+        properties.push(format!(
+            "<exif:GPSLongitude>{}</exif:GPSLongitude>",
+            format_xmp_coordinate(geo.longitude, 'E', 'W')
+        ));
+        if geo.altitude != 0.0 {
+            let altitude_ref = if geo.altitude >= 0.0 { 0 } else { 1 };
+            properties.push(format!(
+                "<exif:GPSAltitudeRef>{}</exif:GPSAltitudeRef>",
+                altitude_ref
+            ));
+            properties.push(format!(
+                "<exif:GPSAltitude>{}/1000</exif:GPSAltitude>",
+                (geo.altitude.abs() * 1000.0).round() as u64
+            ));
+        }
+    }
+
+    let description = google_meta.description.trim();
+    if !description.is_empty() {
+        properties.push(format!(
+            "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>",
+            escape_xml(description)
+        ));
+    }
+
+    properties.extend(extra_properties(google_meta, keywords, options));
+    Ok(xmp_packet(&properties))
+}
+
+/// Builds an XMP packet with only the metadata that EXIF has no tags for, such as people,
+/// to go alongside embedded EXIF. Returns `None` if there is none of it.
+pub fn google_metadata_to_xmp_extras(
+    google_meta: &GoogleSupplementalMetadata,
+    keywords: &[String],
+    options: &MetadataOptions,
+) -> Option<String> {
+    let properties = extra_properties(google_meta, keywords, options);
+    if properties.is_empty() {
+        None
+    } else {
+        Some(xmp_packet(&properties))
+    }
+}
+
+/// Builds an XMP packet with only `keywords`, e.g. the name of the album, for media without a
+/// sidecar. Returns `None` if there are none.
+pub fn keywords_to_xmp(keywords: &[String]) -> Option<String> {
+    if keywords.is_empty() {
+        None
+    } else {
+        Some(xmp_packet(&[bag("dc:subject", keywords)]))
+    }
+}
+
+/// XMP properties for the metadata that EXIF has no tags for: the favorite flag, keywords
+/// and people. People are written both as keywords, which most photo libraries search, and
+/// as the people shown in the photo.
+fn extra_properties(
+    google_meta: &GoogleSupplementalMetadata,
+    extra_keywords: &[String],
+    options: &MetadataOptions,
+) -> Vec<String> {
+    let mut properties = Vec::new();
+    let people = google_meta.people_names();
+    let mut keywords = people.clone();
+    keywords.extend(extra_keywords.iter().cloned());
+
+    if google_meta.favorited == Some(true) {
+        properties.push(format!(
+            "<xmp:Rating>{}</xmp:Rating>",
+            options.favorite_rating
+        ));
+        keywords.extend(options.favorite_keyword.clone());
+    }
+
+    if !keywords.is_empty() {
+        properties.push(bag("dc:subject", &keywords));
+    }
+    if !people.is_empty() {
+        properties.push(bag("Iptc4xmpExt:PersonInImage", &people));
+    }
+
+    properties
+}
+
+/// Formats an unordered array property
+fn bag(property: &str, values: &[String]) -> String {
+    let items: String = values
+        .iter()
+        .map(|value| format!("<rdf:li>{}</rdf:li>", escape_xml(value)))
+        .collect();
+    format!("<{0}><rdf:Bag>{1}</rdf:Bag></{0}>", property, items)
+}
+
+/// Wraps properties in an XMP packet
+fn xmp_packet(properties: &[String]) -> String {
+    let mut xmp = String::from(XMP_HEADER);
+    for property in properties {
+        xmp.push_str("   ");
+        xmp.push_str(property);
+        xmp.push('\n');
+    }
+    xmp.push_str(XMP_FOOTER);
+    xmp
+}
+
+/// Inserts an XMP packet into a JPEG as an APP1 segment, after its JFIF and EXIF segments.
+/// JPEGs that already have XMP are left alone, as their packet may hold data we would lose.
+pub fn embed_xmp_in_jpeg(data: &[u8], xmp: &str) -> Result<Vec<u8>, XmpError> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(XmpError::Malformed("not a JPEG".to_string()));
+    }
+    let segment_length = 2 + JPEG_XMP_NAMESPACE.len() + xmp.len();
+    let segment_length = u16::try_from(segment_length).map_err(|_| {
+        XmpError::Unsupported(format!("XMP packet of {} bytes is too large", xmp.len()))
+    })?;
+
+    // Walk the segments up to the image data, remembering where the APP0/APP1 segments end
+    let mut position = 2;
+    let mut insert_at = 2;
+    loop {
+        let Some(&[0xFF, marker]) = data.get(position..position + 2) else {
+            return Err(XmpError::Malformed(format!("no marker at {}", position)));
+        };
+        match marker {
+            // Fill byte before a marker
+            0xFF => {
+                position += 1;
+                continue;
+            }
+            // Start of scan or end of image
+            0xDA | 0xD9 => break,
+            _ => {}
+        }
+        let length = data
+            .get(position + 2..position + 4)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .filter(|&length| length >= 2)
+            .ok_or_else(|| XmpError::Malformed(format!("bad segment at {}", position)))?;
+        let end = position + 2 + length;
+        let payload = data
+            .get(position + 4..end)
+            .ok_or_else(|| XmpError::Malformed(format!("truncated segment at {}", position)))?;
+
+        if marker == 0xE1 && payload.starts_with(JPEG_XMP_NAMESPACE) {
+            return Err(XmpError::Unsupported("JPEG already has XMP".to_string()));
+        }
+        if marker == 0xE0 || marker == 0xE1 {
+            insert_at = end;
+        }
+        position = end;
+    }
+
+    let mut output = Vec::with_capacity(data.len() + segment_length as usize + 2);
+    output.extend_from_slice(&data[..insert_at]);
+    output.extend_from_slice(&[0xFF, 0xE1]);
+    output.extend_from_slice(&segment_length.to_be_bytes());
+    output.extend_from_slice(JPEG_XMP_NAMESPACE);
+    output.extend_from_slice(xmp.as_bytes());
+    output.extend_from_slice(&data[insert_at..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::parse_google_metadata;
+
+    const SAMPLE_JSON: &str = r#"{
+        "title": "IMG_8238.JPG",
+        "description": "Sunset at <Balaton> & friends",
+        "photoTakenTime": {"timestamp": "1563032119", "formatted": ""},
+        "geoData": {"latitude": 46.7234, "longitude": -17.3456, "altitude": -2.5},
+        "people": [{"name": "Anna"}, {"name": "Bo"}],
+        "favorited": true
+    }"#;
+
+    #[test]
+    fn test_xmp_sidecar_path() {
+        assert_eq!(
+            xmp_sidecar_path(Path::new("out/Album/IMG_8238.JPG")),
+            PathBuf::from("out/Album/IMG_8238.JPG.xmp")
+        );
+    }
+
+    #[test]
+    fn test_format_xmp_coordinate() {
+        assert_eq!(format_xmp_coordinate(46.7234, 'N', 'S'), "46,43.404000N");
+        assert_eq!(format_xmp_coordinate(-17.5, 'E', 'W'), "17,30.000000W");
+    }
+
+    #[test]
+    fn test_google_metadata_to_xmp() {
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let xmp = google_metadata_to_xmp(&meta, &[], &MetadataOptions::default()).unwrap();
+
+        assert!(xmp.starts_with("<?xpacket begin="));
+        assert!(xmp.ends_with("<?xpacket end=\"w\"?>\n"));
+        // Local time at the location, in the Atlantic off Spain
+        assert!(xmp.contains("<exif:DateTimeOriginal>2019-07-13T"));
+        assert!(xmp.contains("<exif:GPSLatitude>46,43.404000N</exif:GPSLatitude>"));
+        assert!(xmp.contains("<exif:GPSLongitude>17,20.736000W</exif:GPSLongitude>"));
+        assert!(xmp.contains("<exif:GPSAltitudeRef>1</exif:GPSAltitudeRef>"));
+        assert!(xmp.contains("<exif:GPSAltitude>2500/1000</exif:GPSAltitude>"));
+        assert!(xmp.contains(">Sunset at &lt;Balaton&gt; &amp; friends</rdf:li>"));
+        assert!(xmp.contains("<xmp:Rating>5</xmp:Rating>"));
+        assert!(xmp.contains(
+            "<dc:subject><rdf:Bag><rdf:li>Anna</rdf:li><rdf:li>Bo</rdf:li></rdf:Bag></dc:subject>"
+        ));
+        assert!(xmp.contains(
+            "<Iptc4xmpExt:PersonInImage><rdf:Bag><rdf:li>Anna</rdf:li><rdf:li>Bo</rdf:li></rdf:Bag>"
+        ));
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(escape_xml("Tom & \"Jerry\" <3"), "Tom &amp; &quot;Jerry&quot; &lt;3");
+        assert_eq!(escape_xml("line\none\ttab"), "line\none\ttab");
+        assert_eq!(escape_xml("bell\u{7}\u{0}\u{1b}"), "bell");
+    }
+
+    #[test]
+    fn test_google_metadata_to_xmp_extras() {
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let xmp = google_metadata_to_xmp_extras(&meta, &[], &MetadataOptions::default()).unwrap();
+        assert!(xmp.contains("<rdf:li>Anna</rdf:li>"));
+        assert!(xmp.contains("<xmp:Rating>5</xmp:Rating>"));
+        // Values EXIF can hold are left to EXIF
+        assert!(!xmp.contains("exif:"));
+        assert!(!xmp.contains("dc:description"));
+
+        let json = r#"{"title": "IMG_8238.JPG", "description": "Sunset", "people": []}"#;
+        let meta = parse_google_metadata(json).unwrap();
+        assert!(google_metadata_to_xmp_extras(&meta, &[], &MetadataOptions::default()).is_none());
+    }
+
+    #[test]
+    fn test_favorite_rating_and_keyword() {
+        let options = MetadataOptions {
+            favorite_rating: 4,
+            favorite_keyword: Some("Favorite".to_string()),
+            ..Default::default()
+        };
+        let meta = parse_google_metadata(SAMPLE_JSON).unwrap();
+        let xmp = google_metadata_to_xmp_extras(&meta, &[], &options).unwrap();
+        assert!(xmp.contains("<xmp:Rating>4</xmp:Rating>"));
+        assert!(xmp.contains(
+            "<dc:subject><rdf:Bag><rdf:li>Anna</rdf:li><rdf:li>Bo</rdf:li><rdf:li>Favorite</rdf:li>"
+        ));
+
+        let json = r#"{"title": "IMG_8238.JPG", "favorited": false}"#;
+        let meta = parse_google_metadata(json).unwrap();
+        assert!(google_metadata_to_xmp_extras(&meta, &[], &options).is_none());
+
+        let json = r#"{"title": "IMG_8238.JPG", "favorited": true}"#;
+        let meta = parse_google_metadata(json).unwrap();
+        let xmp = google_metadata_to_xmp(&meta, &[], &options).unwrap();
+        assert!(
+            xmp.contains("<dc:subject><rdf:Bag><rdf:li>Favorite</rdf:li></rdf:Bag></dc:subject>")
+        );
+        assert!(!xmp.contains("PersonInImage>"));
+    }
+
+    /// Builds a JPEG from segments given as (marker, payload), followed by the image data
+    fn jpeg(segments: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        for (marker, payload) in segments {
+            data.extend_from_slice(&[0xFF, *marker]);
+            data.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+            data.extend_from_slice(payload);
+        }
+        data.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn test_embed_xmp_in_jpeg() {
+        let original = jpeg(&[(0xE0, b"JFIF\0"), (0xE1, b"Exif\0\0"), (0xDB, &[0; 4])]);
+        let embedded = embed_xmp_in_jpeg(&original, "<x:xmpmeta/>").unwrap();
+
+        let xmp_payload = [JPEG_XMP_NAMESPACE, b"<x:xmpmeta/>"].concat();
+        let expected = jpeg(&[
+            (0xE0, b"JFIF\0"),
+            (0xE1, b"Exif\0\0"),
+            (0xE1, &xmp_payload),
+            (0xDB, &[0; 4]),
+        ]);
+        assert_eq!(embedded, expected);
+    }
+
+    #[test]
+    fn test_embed_xmp_in_jpeg_refuses() {
+        let with_xmp = jpeg(&[(0xE1, &[JPEG_XMP_NAMESPACE, b"<x:xmpmeta/>"].concat())]);
+        assert!(matches!(
+            embed_xmp_in_jpeg(&with_xmp, "<x:xmpmeta/>"),
+            Err(XmpError::Unsupported(_))
+        ));
+
+        assert!(matches!(
+            embed_xmp_in_jpeg(b"\x89PNG\r\n", "<x:xmpmeta/>"),
+            Err(XmpError::Malformed(_))
+        ));
+
+        let mut truncated = jpeg(&[(0xE0, b"JFIF\0")]);
+        truncated.truncate(8);
+        assert!(matches!(
+            embed_xmp_in_jpeg(&truncated, "<x:xmpmeta/>"),
+            Err(XmpError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_google_metadata_to_xmp_minimal() {
+        let json = r#"{
+            "title": "IMG_8238.JPG",
+            "photoTakenTime": {"timestamp": "-176342400", "formatted": ""}
+        }"#;
+        let meta = parse_google_metadata(json).unwrap();
+        let options = MetadataOptions {
+            timezone: Some("+02:00".parse().unwrap()),
+            ..Default::default()
+        };
+        let xmp = google_metadata_to_xmp(&meta, &[], &options).unwrap();
+
+        assert!(xmp.contains("<xmp:CreateDate>1964-05-31T02:00:00+02:00</xmp:CreateDate>"));
+        assert!(!xmp.contains("GPS"));
+        assert!(!xmp.contains("dc:description>"));
+        assert!(!xmp.contains("Rating"));
+        assert!(!xmp.contains("PersonInImage>"));
+    }
+}
@@ -3,6 +3,10 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::time::{SystemTime, UNIX_EPOCH};
+use takeout_fixer::archive::Takeout;
+use takeout_fixer::dedup::DedupMode;
+use takeout_fixer::journal::JOURNAL_FILE;
+use takeout_fixer::process::{process_takeout, ProcessError, ProcessOptions};
 
 struct TempDir {
     base: PathBuf,
@@ -37,19 +41,52 @@ impl Drop for TempDir {
 }
 
 fn run_fix(input: &Path, output: &Path) {
+    run_fix_many(&[input], output);
+}
+
+fn run_fix_many(inputs: &[&Path], output: &Path) {
+    run_fix_with_args(inputs, output, &[]);
+}
+
+fn run_fix_with_args(inputs: &[&Path], output: &Path, args: &[&str]) {
+    run_command("fix", inputs, output, args);
+}
+
+fn run_command(command: &str, inputs: &[&Path], output: &Path, args: &[&str]) {
     let exe = env!("CARGO_BIN_EXE_takeout-fixer");
     let status = Command::new(exe)
         .arg("--no-progress")
         .arg("--output")
         .arg(output)
-        .arg("fix")
-        .arg(input)
+        .args(args)
+        .arg(command)
+        .args(inputs)
         .status()
         .expect("Failed to run takeout-fixer");
 
     assert!(status.success(), "takeout-fixer exited with failure");
 }
 
+/// Writes an extracted takeout with the given files below its photo folder, returning its path
+fn write_takeout(base: &Path, files: &[(&str, &str)]) -> PathBuf {
+    let takeout = base.join("takeout-synthetic");
+    for (path, contents) in files {
+        let path = takeout.join("Takeout").join("Google Photos").join(path);
+        fs::create_dir_all(path.parent().unwrap()).expect("Failed to create takeout dir");
+        fs::write(&path, contents).expect("Failed to write takeout file");
+    }
+    takeout
+}
+
+/// Returns sidecar JSON for a media file, with extra top-level fields
+fn sidecar(title: &str, extra: &str) -> String {
+    format!(
+        r#"{{"title": "{}", "photoTakenTime": {{"timestamp": "1563032119", "formatted": ""}}{}}}"#,
+        title, extra
+    )
+}
+
+/// Returns the files below `root`, leaving out the journal of the run that wrote them
 fn collect_files(root: &Path) -> BTreeSet<PathBuf> {
     let mut files = BTreeSet::new();
     let mut stack = vec![root.to_path_buf()];
@@ -61,7 +98,7 @@ fn collect_files(root: &Path) -> BTreeSet<PathBuf> {
             let path = entry.path();
             if path.is_dir() {
                 stack.push(path);
-            } else if path.is_file() {
+            } else if path.is_file() && entry.file_name() != JOURNAL_FILE {
                 let rel = path
                     .strip_prefix(root)
                     .expect("Failed to compute relative path")
@@ -121,3 +158,455 @@ fn integration_input_gzipped_matches_expected_output() {
     run_fix(&input, &output);
     compare_directories(&expected, &output);
 }
+
+#[test]
+fn integration_input_decompressed_matches_expected_output() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let input = root.join("test_data").join("input_decompressed");
+    let expected = root.join("test_data").join("output");
+    let temp = TempDir::new("input-decompressed");
+    let output = temp.output_path();
+
+    run_fix(&input, &output);
+    compare_directories(&expected, &output);
+}
+
+#[test]
+fn integration_mixed_input_matches_expected_output() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let extracted = root
+        .join("test_data")
+        .join("input_decompressed")
+        .join("takeout-20260102T143355Z-3-001");
+    let zipped = root
+        .join("test_data")
+        .join("input_zipped")
+        .join("takeout-20260102T143355Z-3-002.zip");
+    let expected = root.join("test_data").join("output");
+    let temp = TempDir::new("input-mixed");
+    let output = temp.output_path();
+
+    run_fix_many(&[&extracted, &zipped], &output);
+    compare_directories(&expected, &output);
+}
+
+#[test]
+fn integration_trashed_and_archived_media() {
+    let temp = TempDir::new("trashed-archived");
+    let kept = sidecar("kept.mp4", "");
+    let trashed = sidecar("trashed.mp4", r#", "trashed": true"#);
+    let archived = sidecar("archived.mp4", r#", "archived": true"#);
+    let takeout = write_takeout(
+        &temp.base,
+        &[
+            ("Album/kept.mp4", "kept"),
+            ("Album/kept.mp4.supplemental-metadata.json", &kept),
+            ("Album/trashed.mp4", "trashed"),
+            ("Album/trashed.mp4.supplemental-metadata.json", &trashed),
+            ("Album/archived.mp4", "archived"),
+            ("Album/archived.mp4.supplemental-metadata.json", &archived),
+        ],
+    );
+
+    let output = temp.base.join("separate");
+    run_fix_with_args(&[&takeout], &output, &["--trashed", "separate", "--archived", "separate"]);
+    let expected: BTreeSet<PathBuf> = [
+        "Album/kept.mp4",
+        "Trash/Album/trashed.mp4",
+        "Archive/Album/archived.mp4",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(collect_files(&output), expected);
+
+    let output = temp.base.join("skip");
+    run_fix_with_args(&[&takeout], &output, &["--trashed", "skip"]);
+    let expected: BTreeSet<PathBuf> = ["Album/kept.mp4", "Album/archived.mp4"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(collect_files(&output), expected);
+}
+
+#[test]
+fn integration_album_titles() {
+    let temp = TempDir::new("album-titles");
+    let video = sidecar("video.mp4", "");
+    let takeout = write_takeout(
+        &temp.base,
+        &[
+            ("Roma _ Napoli/metadata.json", r#"{"title": "Roma & Napoli", "description": ""}"#),
+            ("Roma _ Napoli/video.mp4", "video"),
+            ("Roma _ Napoli/video.mp4.supplemental-metadata.json", &video),
+            ("Photos from 2019/other.mp4", "other"),
+        ],
+    );
+
+    let output = temp.output_path();
+    run_fix_with_args(
+        &[&takeout],
+        &output,
+        &["--metadata-mode", "xmp", "--album-keywords"],
+    );
+    let expected: BTreeSet<PathBuf> = [
+        "Roma & Napoli/video.mp4",
+        "Roma & Napoli/video.mp4.xmp",
+        "Photos from 2019/other.mp4",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(collect_files(&output), expected);
+
+    let xmp = fs::read_to_string(output.join("Roma & Napoli/video.mp4.xmp")).unwrap();
+    assert!(xmp.contains("<dc:subject><rdf:Bag><rdf:li>Roma &amp; Napoli</rdf:li></rdf:Bag>"));
+}
+
+#[test]
+fn integration_album_keywords_without_sidecar() {
+    let temp = TempDir::new("album-keywords-without-sidecar");
+    let takeout = write_takeout(
+        &temp.base,
+        &[
+            ("Trip/metadata.json", r#"{"title": "Summer Trip", "description": ""}"#),
+            ("Trip/clip.mp4", "clip"),
+        ],
+    );
+
+    let output = temp.output_path();
+    run_fix_with_args(&[&takeout], &output, &["--album-keywords"]);
+    let expected: BTreeSet<PathBuf> = ["Summer Trip/clip.mp4", "Summer Trip/clip.mp4.xmp"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(collect_files(&output), expected);
+
+    let xmp = fs::read_to_string(output.join("Summer Trip/clip.mp4.xmp")).unwrap();
+    assert!(xmp.contains("<dc:subject><rdf:Bag><rdf:li>Summer Trip</rdf:li></rdf:Bag>"));
+}
+
+#[test]
+fn integration_album_keywords_rejects_dedup() {
+    let temp = TempDir::new("album-keywords-dedup");
+    let options = ProcessOptions {
+        album_keywords: true,
+        dedup: DedupMode::Hardlink,
+        show_progress: false,
+        ..Default::default()
+    };
+    let result = process_takeout(&Takeout::new(), &temp.output_path(), "Takeout/", &options);
+    assert!(matches!(result, Err(ProcessError::InvalidOptions(_))));
+}
+
+#[test]
+fn integration_dedup() {
+    let temp = TempDir::new("dedup");
+    let takeout = write_takeout(
+        &temp.base,
+        &[
+            ("Photos from 2019/a.mp4", "same"),
+            ("Photos from 2019/b.mp4", "samf"),
+            ("Trip/metadata.json", r#"{"title": "Trip", "description": ""}"#),
+            ("Trip/a.mp4", "same"),
+            ("Trip/b.mp4", "other"),
+        ],
+    );
+
+    let hardlinked = temp.base.join("hardlinked");
+    run_fix_with_args(&[&takeout], &hardlinked, &["--dedup", "hardlink"]);
+    let expected: BTreeSet<PathBuf> = [
+        "Photos from 2019/a.mp4",
+        "Photos from 2019/b.mp4",
+        "Trip/a.mp4",
+        "Trip/b.mp4",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(collect_files(&hardlinked), expected);
+    assert_eq!(fs::read(hardlinked.join("Trip/a.mp4")).unwrap(), b"same");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let inode = |path: &str| fs::metadata(hardlinked.join(path)).unwrap().ino();
+        assert_eq!(inode("Trip/a.mp4"), inode("Photos from 2019/a.mp4"));
+        assert_ne!(inode("Trip/b.mp4"), inode("Photos from 2019/b.mp4"));
+    }
+
+    let manifest = temp.base.join("manifest");
+    run_fix_with_args(&[&takeout], &manifest, &["--dedup", "manifest"]);
+    let expected: BTreeSet<PathBuf> = [
+        "Photos from 2019/a.mp4",
+        "Photos from 2019/b.mp4",
+        "Trip/b.mp4",
+        "album-manifest.json",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(collect_files(&manifest), expected);
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(manifest.join("album-manifest.json")).unwrap())
+            .unwrap();
+    assert_eq!(json, serde_json::json!({"Trip": ["Photos from 2019/a.mp4"]}));
+}
+
+#[test]
+fn integration_dedup_keeps_written_copy() {
+    let temp = TempDir::new("dedup-written-copy");
+    let trashed = sidecar("d.mp4", r#", "trashed": true"#);
+    let takeout = write_takeout(
+        &temp.base,
+        &[
+            // The album is processed before the year folder it should link to
+            ("A trip/metadata.json", r#"{"title": "A trip", "description": ""}"#),
+            ("A trip/c.mp4", "cccc"),
+            ("A trip/d.mp4", "dddd"),
+            ("Photos from 2019/c.mp4", "cccc"),
+            ("Photos from 2019/d.mp4", "dddd"),
+            ("Photos from 2019/d.mp4.supplemental-metadata.json", &trashed),
+        ],
+    );
+
+    let output = temp.output_path();
+    run_fix_with_args(
+        &[&takeout],
+        &output,
+        &["--dedup", "symlink", "--trashed", "skip", "--jobs", "1"],
+    );
+    let expected: BTreeSet<PathBuf> = ["A trip/c.mp4", "A trip/d.mp4", "Photos from 2019/c.mp4"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(collect_files(&output), expected);
+
+    // The trashed copy was skipped, so its album copy is written instead of linked to it
+    let is_symlink =
+        |path: &str| fs::symlink_metadata(output.join(path)).unwrap().file_type().is_symlink();
+    assert!(!is_symlink("A trip/d.mp4"));
+    assert_eq!(fs::read(output.join("A trip/d.mp4")).unwrap(), b"dddd");
+
+    // The copy outside the album is kept, even though the album's was written first
+    assert!(is_symlink("A trip/c.mp4"));
+    assert!(!is_symlink("Photos from 2019/c.mp4"));
+    assert_eq!(fs::read(output.join("A trip/c.mp4")).unwrap(), b"cccc");
+}
+
+#[test]
+fn integration_date_layout() {
+    let temp = TempDir::new("date-layout");
+    let video = sidecar("video.mp4", "");
+    let takeout = write_takeout(
+        &temp.base,
+        &[
+            ("Trip/video.mp4", "video"),
+            ("Trip/video.mp4.supplemental-metadata.json", &video),
+            ("Photos from 2020/undated.mp4", "undated"),
+        ],
+    );
+
+    // Media without a sidecar falls back to when it was last modified in the archive
+    let undated = takeout.join("Takeout/Google Photos/Photos from 2020/undated.mp4");
+    let modified = UNIX_EPOCH + std::time::Duration::from_secs(1_580_515_200); // 2020-02-01
+    fs::File::options()
+        .write(true)
+        .open(&undated)
+        .and_then(|file| file.set_modified(modified))
+        .expect("Failed to date takeout file");
+
+    let output = temp.output_path();
+    run_fix_with_args(&[&takeout], &output, &["--layout", "{year}/{month}/{album}/{filename}"]);
+    let expected: BTreeSet<PathBuf> = [
+        "2019/07/Trip/video.mp4",
+        "2020/02/Photos from 2020/undated.mp4",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(collect_files(&output), expected);
+}
+
+#[test]
+fn integration_output_collisions() {
+    let temp = TempDir::new("collisions");
+    let takeout = write_takeout(
+        &temp.base,
+        &[
+            ("Party/photo.mp4", "party"),
+            ("Photos from 2019/PHOTO.mp4", "larger photo"),
+            ("Trip/photo.mp4", "trip"),
+        ],
+    );
+
+    let suffixed = temp.base.join("suffixed");
+    // Paths are planned in archive-path order, however many workers write them
+    run_fix_with_args(&[&takeout], &suffixed, &["--jobs", "4", "--layout", "{filename}"]);
+    let expected: BTreeSet<PathBuf> = ["photo.mp4", "PHOTO (1).mp4", "photo (2).mp4"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(collect_files(&suffixed), expected);
+    assert_eq!(fs::read_to_string(suffixed.join("photo.mp4")).unwrap(), "party");
+    assert_eq!(fs::read_to_string(suffixed.join("PHOTO (1).mp4")).unwrap(), "larger photo");
+    assert_eq!(fs::read_to_string(suffixed.join("photo (2).mp4")).unwrap(), "trip");
+
+    let larger = temp.base.join("larger");
+    run_fix_with_args(
+        &[&takeout],
+        &larger,
+        &["--layout", "{filename}", "--on-collision", "keep-larger"],
+    );
+    let files = collect_files(&larger);
+    assert_eq!(files.len(), 1);
+    let kept = files.iter().next().unwrap();
+    assert_eq!(fs::read_to_string(larger.join(kept)).unwrap(), "larger photo");
+}
+
+#[test]
+fn integration_resume() {
+    let temp = TempDir::new("resume");
+    let takeout = write_takeout(
+        &temp.base,
+        &[("Trip/a.mp4", "aaaa"), ("Trip/b.mp4", "bbbb"), ("Trip/c.mp4", "cccc")],
+    );
+    let output = temp.output_path();
+    run_fix_with_args(&[&takeout], &output, &[]);
+
+    // Interrupt the run: c.mp4 was never recorded and b.mp4 was cut short afterwards
+    let journal_path = output.join(JOURNAL_FILE);
+    let journal = fs::read_to_string(&journal_path).unwrap();
+    let journal: String = journal
+        .lines()
+        .filter(|line| !line.contains("c.mp4"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    fs::write(&journal_path, journal).unwrap();
+    fs::remove_file(output.join("Trip/c.mp4")).unwrap();
+    fs::write(output.join("Trip/b.mp4"), "bb").unwrap();
+
+    let untouched = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    fs::File::options()
+        .write(true)
+        .open(output.join("Trip/a.mp4"))
+        .and_then(|file| file.set_modified(untouched))
+        .unwrap();
+
+    run_fix_with_args(&[&takeout], &output, &["--resume"]);
+    assert_eq!(fs::read_to_string(output.join("Trip/b.mp4")).unwrap(), "bbbb");
+    assert_eq!(fs::read_to_string(output.join("Trip/c.mp4")).unwrap(), "cccc");
+    let modified = fs::metadata(output.join("Trip/a.mp4")).unwrap().modified().unwrap();
+    assert_eq!(modified, untouched);
+    assert_eq!(collect_files(&output).len(), 3);
+}
+
+#[test]
+fn integration_update() {
+    let temp = TempDir::new("update");
+    let output = temp.output_path();
+    let first = write_takeout(
+        &temp.base.join("first"),
+        &[
+            ("Trip/a.mp4", "aaaa"),
+            ("Trip/a.mp4.supplemental-metadata.json", &sidecar("a.mp4", "")),
+            ("Trip/b.mp4", "bbbb"),
+            ("Trip/b.mp4.supplemental-metadata.json", &sidecar("b.mp4", "")),
+        ],
+    );
+    run_fix_with_args(&[&first], &output, &["--layout", "{year}/{filename}"]);
+
+    // The next export has everything again, with one sidecar changed and one file added
+    let moved_b = r#"{"title": "b.mp4", "photoTakenTime": {"timestamp": "1600000000", "formatted": ""}}"#;
+    let second = write_takeout(
+        &temp.base.join("second"),
+        &[
+            ("Trip/a.mp4", "aaaa"),
+            ("Trip/a.mp4.supplemental-metadata.json", &sidecar("a.mp4", "")),
+            ("Trip/b.mp4", "bbbb"),
+            ("Trip/b.mp4.supplemental-metadata.json", moved_b),
+            ("Trip/c.mp4", "cccc"),
+        ],
+    );
+
+    let untouched = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    fs::File::options()
+        .write(true)
+        .open(output.join("2019/a.mp4"))
+        .and_then(|file| file.set_modified(untouched))
+        .unwrap();
+
+    run_command("update", &[&second], &output, &["--layout", "{year}/{filename}"]);
+    let modified = fs::metadata(output.join("2019/a.mp4")).unwrap().modified().unwrap();
+    assert_eq!(modified, untouched);
+
+    // c.mp4 has no sidecar and is dated by the time it was written to the takeout
+    let files = collect_files(&output);
+    assert_eq!(files.len(), 3);
+    assert!(files.contains(Path::new("2019/a.mp4")));
+    assert!(files.contains(Path::new("2020/b.mp4")));
+    assert!(files.iter().any(|file| file.ends_with("c.mp4")));
+
+    // Output deleted since is written again, even though its sidecar has not changed
+    fs::remove_file(output.join("2019/a.mp4")).unwrap();
+    run_command("update", &[&second], &output, &["--layout", "{year}/{filename}"]);
+    assert_eq!(fs::read(output.join("2019/a.mp4")).unwrap(), b"aaaa");
+
+    // Media whose contents changed is written again, and moved if it is now dated differently
+    let third = write_takeout(&temp.base.join("third"), &[("Trip/c.mp4", "cccccccc")]);
+    let changed = UNIX_EPOCH + std::time::Duration::from_secs(1_430_000_000);
+    fs::File::options()
+        .write(true)
+        .open(third.join("Takeout/Google Photos/Trip/c.mp4"))
+        .and_then(|file| file.set_modified(changed))
+        .unwrap();
+    run_command("update", &[&third], &output, &["--layout", "{year}/{filename}"]);
+    assert_eq!(fs::read(output.join("2015/c.mp4")).unwrap(), b"cccccccc");
+    let files = collect_files(&output);
+    assert_eq!(files.len(), 3);
+    assert!(!files.iter().any(|file| file.ends_with("c.mp4") && !file.starts_with("2015")));
+}
+
+#[test]
+fn integration_dry_run_dates_images_by_exif() {
+    use flate2::write::GzEncoder;
+    use little_exif::exif_tag::ExifTag;
+    use little_exif::filetype::FileExtension;
+    use little_exif::metadata::Metadata;
+
+    let temp = TempDir::new("dry-run-exif");
+    let takeout = write_takeout(&temp.base, &[]);
+    let mut metadata = Metadata::new();
+    metadata.set_tag(ExifTag::DateTimeOriginal("2015:03:04 10:00:00".to_string()));
+    let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xD9];
+    metadata.write_to_vec(&mut jpeg, FileExtension::JPEG).unwrap();
+    let photo = takeout.join("Takeout/Google Photos/Trip/photo.jpg");
+    fs::create_dir_all(photo.parent().unwrap()).unwrap();
+    fs::write(&photo, jpeg).unwrap();
+
+    // The same takeout as an archive that can only be streamed
+    let archive = temp.base.join("takeout.tar.gz");
+    let encoder = GzEncoder::new(fs::File::create(&archive).unwrap(), Default::default());
+    let mut builder = tar::Builder::new(encoder);
+    builder.append_dir_all("Takeout", takeout.join("Takeout")).unwrap();
+    builder.into_inner().unwrap().finish().unwrap();
+
+    for input in [&takeout, &archive] {
+        let output = Command::new(env!("CARGO_BIN_EXE_takeout-fixer"))
+            .args(["--no-progress", "--dry-run", "--layout", "{year}/{filename}", "--output"])
+            .arg(temp.output_path())
+            .arg("fix")
+            .arg(input)
+            .output()
+            .expect("Failed to run takeout-fixer");
+        assert!(output.status.success(), "takeout-fixer exited with failure");
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let expected = Path::new("2015").join("photo.jpg");
+        assert!(
+            stdout.contains(&*expected.to_string_lossy()),
+            "no dry run line for {} in:\n{}",
+            expected.display(),
+            stdout
+        );
+    }
+}